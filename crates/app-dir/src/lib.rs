@@ -21,6 +21,8 @@ const PRODUCT: &'static str = "fantasy";
 pub struct Directories {
     pub base: String,
     pub db: String,
+    pub replays: String,
+    pub scripts: String,
 }
 
 impl Default for Directories {
@@ -29,6 +31,8 @@ impl Default for Directories {
         let base = replace_home(&data_dir, "$BASE");
         Directories {
             db: db_root_path(&base).into_string().unwrap(),
+            replays: replays_root_path(&base).into_string().unwrap(),
+            scripts: scripts_root_path(&base).into_string().unwrap(),
             base: base,
         }
     }
@@ -38,6 +42,8 @@ impl Directories {
     /// Used to initialize creating missing folders
     pub fn create_dirs(&self) -> Result<(), String> {
         fs::create_dir_all(&self.base).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&self.replays).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&self.scripts).map_err(|e| e.to_string())?;
         Ok(())
     }
 }
@@ -69,6 +75,20 @@ pub fn db_root_path<B: AsRef<OsStr> + Sized>(base: B) -> OsString {
     dir.into_os_string()
 }
 
+/// Battle replays storage path relative with base
+pub fn replays_root_path<B: AsRef<OsStr> + Sized>(base: B) -> OsString {
+    let mut dir = Path::new(&base).to_path_buf();
+    dir.push("replays");
+    dir.into_os_string()
+}
+
+/// Rules scripts storage path relative with base
+pub fn scripts_root_path<B: AsRef<OsStr> + Sized>(base: B) -> OsString {
+    let mut dir = Path::new(&base).to_path_buf();
+    dir.push("scripts");
+    dir.into_os_string()
+}
+
 pub fn get_base_file<B, F>(base: B, file_name: F) -> OsString
 where
     B: AsRef<OsStr> + Sized,
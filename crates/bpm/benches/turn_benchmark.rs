@@ -4,24 +4,17 @@ extern crate bpm;
 extern crate core;
 
 use bpm::rules;
-use core::{PlayerInfo, TurnResultMsg};
+use core::{classic_attacks, PlayerInfo, TurnResultMsg};
 use criterion::Criterion;
 
 fn battle_turn_bench(c: &mut Criterion) {
-    c.bench_function("turn", |b| {
+    let mut rng = rules::seeded_rng(0);
+    c.bench_function("turn", move |b| {
         b.iter(|| {
-            let range = vec![15, 70, 96];
+            let attacks = classic_attacks();
             let info = vec![
-                PlayerInfo {
-                    name: String::from("A"),
-                    energy: 100,
-                    power: 100,
-                },
-                PlayerInfo {
-                    name: String::from("B"),
-                    energy: 100,
-                    power: 100,
-                },
+                PlayerInfo::new("A", 100, 100),
+                PlayerInfo::new("B", 100, 100),
             ];
             let hp = vec![100, 100];
             let turn = 0;
@@ -32,9 +25,8 @@ fn battle_turn_bench(c: &mut Criterion) {
                     p2 = if p2 == info.len() - 1 { 0 } else { p2 + 1 };
                 }
                 let mut out_hp: Vec<u64> = hp.iter().cloned().collect();
-                let dice = rules::roll_dice();
-                let (_attack, reducer) = rules::get_cluster_reducer(range.iter(), dice);
-                let dmg = &reducer(info[turn].power);
+                let dice = rules::roll_dice(&mut rng);
+                let dmg = &rules::find_attack(&attacks, dice).map_or(0, |a| a.damage(info[turn].power));
                 let new_hp = rules::process_dmg(&hp[p2], &dmg);
                 out_hp[p2] = new_hp;
                 while out_hp[p2] <= 0 {
@@ -50,6 +42,7 @@ fn battle_turn_bench(c: &mut Criterion) {
                     hp: out_hp,
                     next_turn: p2,
                     winner: winner,
+                    ..Default::default()
                 };
             }
         })
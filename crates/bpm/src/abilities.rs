@@ -0,0 +1,90 @@
+//! Special abilities the players use instead of attacking, spending their
+//! mana and waiting a cooldown before using them again. Interactive players
+//! name the ability, automatic players pick one with a simple strategy.
+use core::{BattleTurnMsg, PlayerInfo, TurnAction};
+use rules;
+
+/// Mana recovered at the start of every turn of the player, as a fraction
+/// of it's pool
+pub const MANA_REGEN_DIVISOR: u64 = 10;
+
+/// Mana and cooldowns of every player, full pools and every ability ready
+/// when the turn carries none
+pub fn pools(msg: &BattleTurnMsg) -> (Vec<u64>, Vec<Vec<u64>>) {
+    let players = msg.info.len();
+    let mana = if msg.mana.len() == players {
+        msg.mana.clone()
+    } else {
+        msg.info.iter().map(|i| i.attributes.mana).collect()
+    };
+    let cooldowns = if msg.cooldowns.len() == players {
+        msg.cooldowns.clone()
+    } else {
+        vec![vec![0; msg.abilities.len()]; players]
+    };
+    (mana, cooldowns)
+}
+
+/// Start of the turn of a player, some mana comes back and the cooldowns
+/// run down
+pub fn recover(info: &PlayerInfo, mana: &mut u64, cooldowns: &mut [u64]) {
+    let pool = info.attributes.mana;
+    *mana = mana.saturating_add(pool / MANA_REGEN_DIVISOR).min(pool);
+    for c in cooldowns.iter_mut() {
+        *c = c.saturating_sub(1);
+    }
+}
+
+/// Index on the catalog of an ability of the player on turn
+pub fn find(msg: &BattleTurnMsg, name: &str) -> Option<usize> {
+    let player = &msg.info[msg.turn];
+    if !player.abilities.iter().any(|a| a.eq_ignore_ascii_case(name)) {
+        return None;
+    }
+    msg.abilities.iter().position(|a| a.name.eq_ignore_ascii_case(name))
+}
+
+// whether the player on turn can use the ability now
+fn ready(msg: &BattleTurnMsg, index: usize, mana: u64, cooldowns: &[u64]) -> bool {
+    msg.abilities[index].cost <= mana && cooldowns.get(index).map_or(true, |&c| c == 0)
+}
+
+/// Ability used by the player on turn, the one it named or the one picked
+/// for an automatic turn. An ability that isn't ready is an attack
+pub fn pick(msg: &BattleTurnMsg, hp: &[u64], mana: u64, cooldowns: &[u64]) -> Option<usize> {
+    match msg.action {
+        Some(TurnAction::Ability(ref name)) => {
+            find(msg, name).filter(|&i| ready(msg, i, mana, cooldowns))
+        }
+        None => choose(msg, hp, mana, cooldowns),
+        _ => None,
+    }
+}
+
+// automatic players heal below half of their max HP, hit every enemy when
+// there are a few and strike hard otherwise
+fn choose(msg: &BattleTurnMsg, hp: &[u64], mana: u64, cooldowns: &[u64]) -> Option<usize> {
+    let player = &msg.info[msg.turn];
+    let usable: Vec<usize> = player
+        .abilities
+        .iter()
+        .filter_map(|name| find(msg, name))
+        .filter(|&i| ready(msg, i, mana, cooldowns))
+        .collect();
+    let ability = |i: &usize| &msg.abilities[*i];
+    let enemies = rules::targets(&msg.info, hp, msg.turn).len();
+    if hp[msg.turn].saturating_mul(2) < player.max_hp() {
+        if let Some(&i) = usable.iter().find(|i| ability(i).heal > 0) {
+            return Some(i);
+        }
+    }
+    if enemies >= 2 {
+        if let Some(&i) = usable.iter().find(|i| ability(i).area && ability(i).offensive()) {
+            return Some(i);
+        }
+    }
+    usable
+        .iter()
+        .cloned()
+        .find(|i| !ability(i).area && ability(i).offensive())
+}
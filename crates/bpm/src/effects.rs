@@ -0,0 +1,117 @@
+//! Status effects lasting some turns of the player they are on, poison
+//! hurting at the start of it's turns, stun skipping them, shields
+//! absorbing damage and buffs adding power. The attack table applies them.
+use core::{ClientAction, Effect, EffectKind};
+
+/// Effects of every player, nobody has any when the turn carries none
+pub fn of_players(effects: &[Vec<Effect>], players: usize) -> Vec<Vec<Effect>> {
+    if effects.len() == players {
+        effects.to_vec()
+    } else {
+        vec![Vec::new(); players]
+    }
+}
+
+/// Amount of an effect on a player, 0 without it
+pub fn amount(effects: &[Effect], kind: EffectKind) -> u64 {
+    effects
+        .iter()
+        .filter(|e| e.kind == kind)
+        .fold(0, |total, e| total.saturating_add(e.amount))
+}
+
+/// Whether a player is stunned and loses it's next turn
+pub fn stunned(effects: &[Vec<Effect>], player: usize) -> bool {
+    effects
+        .get(player)
+        .map_or(false, |e| e.iter().any(|e| e.kind == EffectKind::Stun))
+}
+
+/// Puts an effect on a player, an effect already there gets the longest
+/// duration and the amounts added up, or the strongest one when the kind
+/// doesn't stack
+pub fn apply(effects: &mut Vec<Effect>, effect: &Effect) {
+    match effects.iter_mut().find(|e| e.kind == effect.kind) {
+        Some(e) => {
+            e.amount = if effect.kind.stacks() {
+                e.amount.saturating_add(effect.amount)
+            } else {
+                e.amount.max(effect.amount)
+            };
+            e.turns = e.turns.max(effect.turns);
+        }
+        None => effects.push(effect.clone()),
+    }
+}
+
+/// What the effects of the player on turn did at it's start
+#[derive(Debug, Default)]
+pub struct Tick {
+    pub stunned: bool,
+    /// Power added to the attack of the turn
+    pub buff: u64,
+    pub actions: Vec<ClientAction>,
+}
+
+/// Effects acting at the start of the turn of a player, poison takes it's
+/// HP and durations run down. Effects expiring still act on this turn
+pub fn tick(effects: &mut Vec<Effect>, hp: &mut u64, number: u64, player: usize, name: &str) -> Tick {
+    let mut tick = Tick {
+        stunned: effects.iter().any(|e| e.kind == EffectKind::Stun),
+        buff: amount(effects, EffectKind::Buff),
+        actions: Vec::new(),
+    };
+    let poison = amount(effects, EffectKind::Poison);
+    if poison > 0 {
+        *hp = hp.saturating_sub(poison);
+        tick.actions.push(ClientAction::EffectTick {
+            turn: number,
+            player,
+            player_name: String::from(name),
+            effect: EffectKind::Poison,
+            amount: poison,
+            hp: *hp,
+        });
+    }
+    if tick.stunned && *hp > 0 {
+        tick.actions.push(ClientAction::EffectTick {
+            turn: number,
+            player,
+            player_name: String::from(name),
+            effect: EffectKind::Stun,
+            amount: 0,
+            hp: *hp,
+        });
+    }
+    for e in effects.iter_mut() {
+        e.turns = e.turns.saturating_sub(1);
+        if e.turns == 0 {
+            tick.actions.push(expired(name, e.kind));
+        }
+    }
+    effects.retain(|e| e.turns > 0);
+    tick
+}
+
+/// Shields of the defender absorbing the damage, a shield is gone when it
+/// absorbed all it's amount. Returns the damage left for the HP
+pub fn absorb(effects: &mut Vec<Effect>, damage: u64, name: &str, actions: &mut Vec<ClientAction>) -> u64 {
+    let mut left = damage;
+    for e in effects.iter_mut().filter(|e| e.kind == EffectKind::Shield) {
+        let absorbed = e.amount.min(left);
+        e.amount -= absorbed;
+        left -= absorbed;
+        if absorbed > 0 && e.amount == 0 {
+            actions.push(expired(name, e.kind));
+        }
+    }
+    effects.retain(|e| e.kind != EffectKind::Shield || e.amount > 0);
+    left
+}
+
+fn expired(name: &str, effect: EffectKind) -> ClientAction {
+    ClientAction::EffectExpired {
+        player_name: String::from(name),
+        effect,
+    }
+}
@@ -0,0 +1,95 @@
+//! Turn order schedulers picking who acts after a turn from the speed of
+//! the players. Without a turn order the rule set decides, the classic
+//! rules rotating by index with the defender striking back.
+use core::{BattleTurnMsg, PlayerInfo, Schedule, TurnOrder};
+use rand::Rng;
+use rules;
+use std::cmp::Reverse;
+
+/// Action gauge a player fills with it's speed before acting in atb order
+pub const ATB_GAUGE: u64 = 100;
+
+/// Players alive from the fastest to the slowest, ties broken by a dice
+/// roll of every player
+pub fn round<R: Rng>(info: &[PlayerInfo], hp: &[u64], rng: &mut R) -> Vec<usize> {
+    let mut order: Vec<(u64, u8, usize)> = (0..hp.len())
+        .filter(|&i| hp[i] > 0)
+        .map(|i| (info[i].speed(), rules::roll_dice(rng), i))
+        .collect();
+    order.sort_by_key(|&(speed, dice, i)| (Reverse(speed), Reverse(dice), i));
+    order.into_iter().map(|(_, _, i)| i).collect()
+}
+
+// next player of the round, a new round starts when every player alive
+// already acted
+fn next_initiative<R: Rng>(
+    info: &[PlayerInfo],
+    hp: &[u64],
+    schedule: &mut Schedule,
+    rng: &mut R,
+) -> Option<usize> {
+    schedule.queue.retain(|&i| hp[i] > 0);
+    if schedule.queue.is_empty() {
+        schedule.queue = round(info, hp, rng);
+    }
+    if schedule.queue.is_empty() {
+        None
+    } else {
+        Some(schedule.queue.remove(0))
+    }
+}
+
+// every player alive fills it's gauge with it's speed until a gauge is
+// full, the fullest one acts spending a whole gauge
+fn next_atb(info: &[PlayerInfo], hp: &[u64], schedule: &mut Schedule) -> Option<usize> {
+    if schedule.gauge.len() != hp.len() {
+        schedule.gauge = vec![0; hp.len()];
+    }
+    let alive: Vec<usize> = (0..hp.len()).filter(|&i| hp[i] > 0).collect();
+    if alive.is_empty() {
+        return None;
+    }
+    for i in (0..hp.len()).filter(|&i| hp[i] == 0) {
+        schedule.gauge[i] = 0;
+    }
+    loop {
+        let gauge = &mut schedule.gauge;
+        let full = alive
+            .iter()
+            .cloned()
+            .filter(|&i| gauge[i] >= ATB_GAUGE)
+            .max_by_key(|&i| (gauge[i], Reverse(i)));
+        if let Some(i) = full {
+            gauge[i] -= ATB_GAUGE;
+            return Some(i);
+        }
+        for &i in &alive {
+            // a player never stands still
+            gauge[i] += info[i].speed().max(1);
+        }
+    }
+}
+
+/// Player acting next by the turn order, None when the rule set decides
+pub fn next_turn<R: Rng>(
+    order: Option<TurnOrder>,
+    info: &[PlayerInfo],
+    hp: &[u64],
+    schedule: &mut Schedule,
+    rng: &mut R,
+) -> Option<usize> {
+    match order {
+        None | Some(TurnOrder::Rotation) => None,
+        Some(TurnOrder::Initiative) => next_initiative(info, hp, schedule, rng),
+        Some(TurnOrder::Atb) => next_atb(info, hp, schedule),
+    }
+}
+
+/// Opens a battle with the first player to act by the turn order
+pub fn open<R: Rng>(msg: &mut BattleTurnMsg, rng: &mut R) {
+    let mut schedule = Schedule::default();
+    if let Some(first) = next_turn(msg.turn_order, &msg.info, &msg.hp, &mut schedule, rng) {
+        msg.turn = first;
+        msg.schedule = schedule;
+    }
+}
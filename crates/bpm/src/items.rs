@@ -0,0 +1,56 @@
+//! Items the players consume instead of attacking, potions healing them
+//! and bombs hurting their enemies. Equipment adds it's stats when the
+//! player enters the battle and never reaches the inventory.
+use core::{BattleTurnMsg, TurnAction};
+
+/// Items every player has left, the consumables it carries when the turn
+/// carries none
+pub fn inventories(msg: &BattleTurnMsg) -> Vec<Vec<String>> {
+    if msg.inventory.len() == msg.info.len() {
+        return msg.inventory.clone();
+    }
+    msg.info
+        .iter()
+        .map(|i| {
+            i.items
+                .iter()
+                .filter(|name| find(msg, name).map_or(false, |c| msg.items[c].consumable()))
+                .cloned()
+                .collect()
+        }).collect()
+}
+
+/// Index on the catalog of an item
+pub fn find(msg: &BattleTurnMsg, name: &str) -> Option<usize> {
+    msg.items.iter().position(|i| i.name.eq_ignore_ascii_case(name))
+}
+
+/// Index on the catalog of an item the player on turn has left
+pub fn carried(msg: &BattleTurnMsg, inventory: &[String], name: &str) -> Option<usize> {
+    if inventory.iter().any(|i| i.eq_ignore_ascii_case(name)) {
+        find(msg, name)
+    } else {
+        None
+    }
+}
+
+/// Item consumed by the player on turn, the one it named or a potion an
+/// automatic player drinks below a quarter of it's max HP. A missing item
+/// is an attack
+pub fn pick(msg: &BattleTurnMsg, hp: &[u64], inventory: &[String]) -> Option<usize> {
+    match msg.action {
+        Some(TurnAction::Item(ref name)) => carried(msg, inventory, name),
+        None if hp[msg.turn].saturating_mul(4) < msg.info[msg.turn].max_hp() => inventory
+            .iter()
+            .filter_map(|name| find(msg, name))
+            .find(|&i| msg.items[i].heal > 0),
+        _ => None,
+    }
+}
+
+/// Takes one of the item out of the inventory
+pub fn consume(inventory: &mut Vec<String>, name: &str) {
+    if let Some(i) = inventory.iter().position(|n| n.eq_ignore_ascii_case(name)) {
+        inventory.remove(i);
+    }
+}
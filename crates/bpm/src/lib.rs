@@ -22,19 +22,151 @@ extern crate failure;
 extern crate actix;
 extern crate core;
 extern crate engine_io;
+extern crate futures;
 extern crate rand;
+extern crate parity_wasm;
+extern crate pwasm_utils;
+extern crate rhai;
+extern crate serde_json;
+extern crate wasmi;
+#[macro_use]
 extern crate crossbeam_channel as channel;
+#[macro_use]
+extern crate serde_derive;
 
+pub mod abilities;
+pub mod effects;
+pub mod initiative;
+pub mod items;
 pub mod rules;
+pub mod ruleset;
+pub mod script;
+pub mod simulation;
+pub mod sink;
+pub mod wasm;
 
 use actix::{Actor, Addr, Handler, SyncContext};
 use core::{
-    BattleAnnounceMsg, BattleTurnMsg, BattleWarmUpMsg, GetPlayerInfoMsg, PlayerInfo, TurnResultMsg,
-    WinnerMsg
+    AskActionMsg, BattleAnnounceMsg, BattleTurnMsg, BattleWarmUpMsg, GetPlayerInfoMsg, PlayerInfo,
+    PlayerInput, RematchMsg, ReplayEvent, Targeting, TurnAction, TurnResultMsg, WinnerMsg
 };
 use engine_io::EnginePipeIo;
+use futures::Future;
+use rules::DiceRng;
+use ruleset::BattleRules;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sink::{BattleEventSink, IoSink};
+
+/// BPM Actor holding the IO connector address, the player input
+/// channel, the rule set and the dice generator seeded at creation so
+/// every battle can be reproduced from it's seed, a rematch is seeded
+/// again. Interactive battles
+/// ask the player on turn for it's action waiting up to `action_timeout`,
+/// a chosen target is waited up to `target_timeout`
+pub struct Bpm {
+    pub io: Addr<EnginePipeIo>,
+    pub input: channel::Receiver<PlayerInput>,
+    pub seed: u64,
+    pub rng: DiceRng,
+    /// Games announced so far
+    pub games: u64,
+    pub rules: Arc<BattleRules>,
+    pub action_timeout: Option<Duration>,
+    pub target_timeout: Option<Duration>,
+}
+
+impl Bpm {
+    pub fn new(
+        io: Addr<EnginePipeIo>,
+        input: channel::Receiver<PlayerInput>,
+        seed: u64,
+        rules: Arc<BattleRules>,
+        action_timeout: Option<Duration>,
+        target_timeout: Option<Duration>,
+    ) -> Self {
+        Bpm {
+            io,
+            input,
+            seed,
+            rng: rules::seeded_rng(seed),
+            games: 0,
+            rules,
+            action_timeout,
+            target_timeout,
+        }
+    }
 
-pub struct Bpm(pub Addr<EnginePipeIo>, pub channel::Receiver<PlayerInfo>);
+    /// Rules events go to the IO connector
+    fn sink(&self) -> IoSink {
+        IoSink(self.io.clone())
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Option<PlayerInput> {
+        recv_until(&self.input, deadline)
+    }
+
+    // the player on turn is asked until it answers a valid action, when
+    // time is up it plays automatically
+    fn ask_action(&self, msg: &mut BattleTurnMsg, timeout: Duration) -> Result<(), failure::Error> {
+        let deadline = Instant::now() + timeout;
+        let mut now = Instant::now();
+        while now < deadline {
+            let prompt = rules::ask_action(msg, (deadline - now).as_secs());
+            let answer = self
+                .io
+                .send(AskActionMsg {
+                    player: msg.info[msg.turn].name.clone(),
+                    prompt,
+                    timeout: deadline - now,
+                }).wait()??;
+            match answer {
+                Some(TurnAction::Attack(Some(name))) => {
+                    // the name of an ability of the player uses it, the
+                    // name of an item it carries consumes it
+                    if abilities::find(msg, &name).is_some() {
+                        msg.action = Some(TurnAction::Ability(name));
+                        return Ok(());
+                    }
+                    let inventory = items::inventories(msg).swap_remove(msg.turn);
+                    if items::carried(msg, &inventory, &name).is_some() {
+                        msg.action = Some(TurnAction::Item(name));
+                        return Ok(());
+                    }
+                    if let Some(target) = rules::parse_target(msg, &name) {
+                        msg.target = Some(target);
+                        msg.action = Some(TurnAction::Attack(Some(name)));
+                        return Ok(());
+                    }
+                }
+                Some(action) => {
+                    msg.action = Some(action);
+                    return Ok(());
+                }
+                None => break,
+            }
+            now = Instant::now();
+        }
+        rules::action_timeout(msg, &mut self.sink())
+    }
+}
+
+// next player input, PlayerInput::Timeout when the deadline passes
+// first and None when the input is closed
+fn recv_until(input: &channel::Receiver<PlayerInput>, deadline: Option<Instant>) -> Option<PlayerInput> {
+    let deadline = match deadline {
+        Some(d) => d,
+        None => return input.recv(),
+    };
+    let now = Instant::now();
+    if now >= deadline {
+        return Some(PlayerInput::Timeout);
+    }
+    select! {
+        recv(input, msg) => msg,
+        recv(channel::after(deadline - now)) => Some(PlayerInput::Timeout),
+    }
+}
 
 /// Turn EnginePipeIo into Actor enabled
 impl Actor for Bpm {
@@ -43,11 +175,25 @@ impl Actor for Bpm {
 
 /// Message handling for type GetPlayerInfoMsg
 impl Handler<GetPlayerInfoMsg> for Bpm {
-    type Result = Result<PlayerInfo, failure::Error>;
+    type Result = Result<PlayerInput, failure::Error>;
 
     #[cfg_attr(feature = "flame_it", flame)]
-    fn handle(&mut self, _msg: GetPlayerInfoMsg, _ctx: &mut Self::Context) -> Self::Result {
-        self.1.recv().ok_or(format_err!("channel closed"))
+    fn handle(&mut self, msg: GetPlayerInfoMsg, _ctx: &mut Self::Context) -> Self::Result {
+        let deadline = msg.timeout.map(|t| Instant::now() + t);
+        let input = match self.recv(deadline).ok_or(format_err!("channel closed"))? {
+            PlayerInput::Answer(_) => {
+                PlayerInput::Invalid(String::from("Player info expected, got a yes / no answer"))
+            }
+            PlayerInput::Choice(c) => match c.parse::<PlayerInfo>() {
+                Ok(info) => PlayerInput::Info(info),
+                Err(e) => PlayerInput::Invalid(format!("{}", e)),
+            },
+            input => input,
+        };
+        if let PlayerInput::Invalid(ref reason) = input {
+            rules::invalid_player_info(reason.clone(), &mut self.sink())?;
+        }
+        Ok(input)
     }
 }
 
@@ -57,17 +203,28 @@ impl Handler<BattleWarmUpMsg> for Bpm {
 
     #[cfg_attr(feature = "flame_it", flame)]
     fn handle(&mut self, msg: BattleWarmUpMsg, _ctx: &mut Self::Context) -> Self::Result {
-        rules::battle_warm_up(msg.players, msg.current_players, self.0.clone())
+        rules::battle_warm_up(msg.players, msg.current_players, &mut self.sink())
     }
 }
 
 /// Message handling for type BattleAnnounceMsg
 impl Handler<BattleAnnounceMsg> for Bpm {
-    type Result = Result<(), failure::Error>;
+    type Result = Result<BattleTurnMsg, failure::Error>;
 
     #[cfg_attr(feature = "flame_it", flame)]
     fn handle(&mut self, msg: BattleAnnounceMsg, _ctx: &mut Self::Context) -> Self::Result {
-        rules::battle_announce(msg.0, self.0.clone())
+        // every game after the first of a series plays with it's own seed
+        if self.games > 0 {
+            rules::next_game(&mut self.seed, &mut self.rng);
+        }
+        self.games += 1;
+        let mut first = msg.0;
+        let player_names = first.info.iter().map(|i| i.name.clone()).collect();
+        rules::battle_announce(player_names, self.seed, &mut self.sink())?;
+        // the turn order picks the first player, the next turns carry the
+        // schedule on
+        initiative::open(&mut first, &mut self.rng);
+        Ok(first)
     }
 }
 
@@ -76,8 +233,38 @@ impl Handler<BattleTurnMsg> for Bpm {
     type Result = Result<TurnResultMsg, failure::Error>;
 
     #[cfg_attr(feature = "flame_it", flame)]
-    fn handle(&mut self, msg: BattleTurnMsg, _ctx: &mut Self::Context) -> Self::Result {
-        rules::battle_turn(&msg.range, &msg.info, &msg.hp, msg.turn, self.0.clone())
+    fn handle(&mut self, mut msg: BattleTurnMsg, _ctx: &mut Self::Context) -> Self::Result {
+        let mut sink = self.sink();
+        // nobody is asked with no enemy left or while stunned
+        let acts = !rules::targets(&msg.info, &msg.hp, msg.turn).is_empty()
+            && !effects::stunned(&msg.effects, msg.turn);
+        match self.action_timeout {
+            // interactive battles ask for the whole action
+            Some(timeout) if acts && msg.action.is_none() => self.ask_action(&mut msg, timeout)?,
+            Some(_) => (),
+            // the attacker is asked until it names a target, without any
+            // more input the rule set picks it
+            None if rules::targeting(&msg) == Some(Targeting::Chosen) => {
+                let deadline = self.target_timeout.map(|t| Instant::now() + t);
+                while msg.target.is_none() && acts {
+                    rules::ask_target(&msg, &mut sink)?;
+                    match self.recv(deadline) {
+                        Some(PlayerInput::Choice(c)) => msg.target = rules::parse_target(&msg, &c),
+                        Some(PlayerInput::Timeout) => {
+                            rules::action_timeout(&msg, &mut sink)?;
+                            break;
+                        }
+                        Some(_) => continue,
+                        None => break,
+                    }
+                }
+            }
+            None => (),
+        }
+        let result = rules::battle_turn(&*self.rules, &msg, &mut self.rng, &mut sink)?;
+        // every turn with it's result is saved on the battle replay
+        sink.replay(ReplayEvent::Turn(msg, result.clone()));
+        Ok(result)
     }
 }
 
@@ -87,31 +274,730 @@ impl Handler<WinnerMsg> for Bpm {
 
     #[cfg_attr(feature = "flame_it", flame)]
     fn handle(&mut self, msg: WinnerMsg, _ctx: &mut Self::Context) -> Self::Result {
-        rules::battle_over(msg.0, msg.1, self.0.clone())
+        rules::battle_over(msg.winner, &msg.info, &msg.hp, &mut self.sink())
+    }
+}
+
+/// Message handling for type RematchMsg
+impl Handler<RematchMsg> for Bpm {
+    type Result = Result<Option<bool>, failure::Error>;
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn handle(&mut self, msg: RematchMsg, _ctx: &mut Self::Context) -> Self::Result {
+        // players are asked again until a yes / no answer, without
+        // any more input there is nobody left to play
+        let deadline = msg.timeout.map(|t| Instant::now() + t);
+        loop {
+            rules::ask_rematch(&mut self.sink())?;
+            match self.recv(deadline) {
+                Some(PlayerInput::Answer(answer)) => return Ok(Some(answer)),
+                Some(PlayerInput::Timeout) => return Ok(None),
+                Some(_) => continue,
+                None => return Ok(Some(false)),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use core::{
+        classic_abilities, classic_attacks, classic_classes, classic_items, equip, Attack,
+        BattleTurnMsg, ClientAction, Effect, EffectKind, PlayerInfo, PlayerInput, ReplayEvent,
+        Schedule, Targeting, TurnAction, TurnOrder,
+    };
+    use abilities;
+    use effects;
+    use channel;
+    use recv_until;
     use rules;
+    use ruleset;
+    use simulation;
+    use sink::EventLog;
+    use std::time::{Duration, Instant};
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
 
+    // first turn of a classic battle between the players of the lines
+    fn battle(players: &[&str], hp: Vec<u64>) -> BattleTurnMsg {
+        let info = players.iter().map(|p| p.parse().unwrap()).collect();
+        BattleTurnMsg::new(classic_attacks(), info, hp)
+    }
+
+    // classic rules attacking the player after the one on turn, allies
+    // or not
+    struct Traitor;
+
+    impl ruleset::BattleRules for Traitor {
+        fn name(&self) -> &str {
+            "traitor"
+        }
+
+        fn select_target(&self, _info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+            (turn + 1) % hp.len()
+        }
+
+        fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack> {
+            ruleset::Classic.resolve_attack(attacks, dice)
+        }
+
+        fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64 {
+            ruleset::Classic.damage(attack, attacker)
+        }
+
+        fn next_turn(&self, hp: &[u64], turn: usize, defender: usize) -> usize {
+            ruleset::Classic.next_turn(hp, turn, defender)
+        }
+
+        fn winner(&self, hp: &[u64], turn: usize) -> Option<usize> {
+            ruleset::Classic.winner(hp, turn)
+        }
+    }
+
+    // classic rules where the player before the one on turn plays next
+    struct Backwards;
+
+    impl ruleset::BattleRules for Backwards {
+        fn name(&self) -> &str {
+            "backwards"
+        }
+
+        fn select_target(&self, info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+            ruleset::Classic.select_target(info, hp, turn)
+        }
+
+        fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack> {
+            ruleset::Classic.resolve_attack(attacks, dice)
+        }
+
+        fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64 {
+            ruleset::Classic.damage(attack, attacker)
+        }
+
+        fn next_turn(&self, hp: &[u64], turn: usize, _defender: usize) -> usize {
+            (turn + hp.len() - 1) % hp.len()
+        }
+
+        fn winner(&self, hp: &[u64], turn: usize) -> Option<usize> {
+            ruleset::Classic.winner(hp, turn)
+        }
+    }
+
+    fn classic_damage(dice: u8, power: u64) -> (String, u64) {
+        let attacks = classic_attacks();
+        let attack = rules::find_attack(&attacks, dice).unwrap();
+        (attack.name.clone(), attack.damage(power))
+    }
+
+    #[test]
+    fn input_deadline_test() {
+        let (sender, input) = channel::bounded(10);
+        let soon = || Some(Instant::now() + Duration::from_millis(20));
+        // input before the deadline is read, without input the deadline passes
+        sender.send(PlayerInput::Answer(true));
+        match recv_until(&input, soon()) {
+            Some(PlayerInput::Answer(a)) => assert!(a),
+            i => panic!("{:?}", i),
+        }
+        let start = Instant::now();
+        match recv_until(&input, soon()) {
+            Some(PlayerInput::Timeout) => assert!(start.elapsed() >= Duration::from_millis(20)),
+            i => panic!("{:?}", i),
+        }
+        // a deadline already passed times out right away
+        match recv_until(&input, Some(start)) {
+            Some(PlayerInput::Timeout) => (),
+            i => panic!("{:?}", i),
+        }
+        // without a deadline the input is waited for until it's closed
+        sender.send(PlayerInput::Answer(false));
+        match recv_until(&input, None) {
+            Some(PlayerInput::Answer(a)) => assert!(!a),
+            i => panic!("{:?}", i),
+        }
+        drop(sender);
+        assert!(recv_until(&input, None).is_none());
+    }
+
     #[test]
     fn regular_rule_standard_test() {
-        let dmg = rules::rule_standard(100);
-        assert_eq!(dmg, 33);
+        assert_eq!(classic_damage(15, 100), (String::from("Standard"), 33));
     }
     #[test]
     fn regular_rule_lucky_test() {
-        let dmg = rules::rule_lucky(120);
-        assert_eq!(dmg, 48);
+        assert_eq!(classic_damage(70, 120), (String::from("Lucky"), 48));
     }
     #[test]
     fn regular_rule_critical_test() {
-        let dmg = rules::rule_critical(120);
-        assert_eq!(dmg, 80);
+        assert_eq!(classic_damage(99, 120), (String::from("Critical"), 80));
+    }
+
+    #[test]
+    fn attack_table_test() {
+        let mut attacks = classic_attacks();
+        assert!(rules::check_attacks(&attacks).is_ok());
+        assert_eq!(classic_damage(14, 100), (String::from("Miss"), 0));
+        attacks.push(Attack::new("Glancing", 90, 100, 1, 10));
+        assert!(rules::check_attacks(&attacks).is_err());
+        attacks.truncate(3);
+        attacks.push(Attack::new("Devastating", 96, 100, 1, 1));
+        assert!(rules::check_attacks(&attacks).is_ok());
+        assert_eq!(rules::find_attack(&attacks, 99).unwrap().damage(100), 100);
+        // dice outside of the table fire no attack
+        attacks.truncate(2);
+        assert!(rules::find_attack(&attacks, 80).is_none());
+    }
+
+    #[test]
+    fn same_seed_same_dice_test() {
+        let mut rng1 = rules::seeded_rng(42);
+        let mut rng2 = rules::seeded_rng(42);
+        let dice1: Vec<u8> = (0..100).map(|_| rules::roll_dice(&mut rng1)).collect();
+        let dice2: Vec<u8> = (0..100).map(|_| rules::roll_dice(&mut rng2)).collect();
+        assert_eq!(dice1, dice2);
+        assert!(dice1.iter().all(|d| *d < 100));
+        // a rematch replays from it's own seed, the series from the first one
+        let (mut seed1, mut seed2) = (42, 42);
+        rules::next_game(&mut seed1, &mut rng1);
+        rules::next_game(&mut seed2, &mut rng2);
+        assert_eq!(seed1, seed2);
+        assert_ne!(seed1, 42);
+        let mut replay = rules::seeded_rng(seed1);
+        let rematch: Vec<u8> = (0..100).map(|_| rules::roll_dice(&mut rng1)).collect();
+        let replayed: Vec<u8> = (0..100).map(|_| rules::roll_dice(&mut replay)).collect();
+        assert_eq!(rematch, replayed);
+    }
+
+    #[test]
+    fn rules_events_test() {
+        let mut log = EventLog::default();
+        rules::battle_announce(vec![String::from("A"), String::from("B")], 42, &mut log).unwrap();
+        let mut rng = rules::seeded_rng(42);
+        let msg = battle(&["A 100 100", "B 100 60"], vec![100, 100]);
+        let result = rules::battle_turn(&ruleset::Classic, &msg, &mut rng, &mut log).unwrap();
+        match (&log.actions[0], &log.actions[1], &log.actions[3]) {
+            (ClientAction::Start, ClientAction::Seed(42), ClientAction::PlayerAction { .. }) => (),
+            a => panic!("{:?}", a),
+        }
+        match log.replay[0] {
+            ReplayEvent::Dice(_) => (),
+            ref e => panic!("{:?}", e),
+        }
+        match log.actions[4] {
+            ClientAction::AttackResult { hp, ref key, .. } => {
+                assert_eq!(hp, result.hp[1]);
+                assert!(key.starts_with("attack-"));
+            }
+            ref a => panic!("{:?}", a),
+        }
+    }
+
+    #[test]
+    fn rule_sets_test() {
+        let info: Vec<PlayerInfo> = vec![
+            "A 100 100".parse().unwrap(),
+            "B 100 60".parse().unwrap(),
+            "C 100 60".parse().unwrap(),
+        ];
+        let hp = vec![100, 80, 20];
+        let classic = ruleset::by_name("classic").unwrap();
+        let focus = ruleset::by_name("focus").unwrap();
+        assert_eq!(classic.select_target(&info, &hp, 0), 1);
+        assert_eq!(focus.select_target(&info, &hp, 0), 2);
+        assert_eq!(classic.select_target(&info, &[100, 0, 20], 0), 2);
+        assert_eq!(classic.winner(&[100, 0, 0], 0), Some(0));
+        assert_eq!(classic.winner(&hp, 0), None);
+        assert!(ruleset::by_name("unknown").is_err());
+    }
+
+    #[test]
+    fn targeting_test() {
+        let mut msg = battle(
+            &["A 100 100", "B 100 60 highest-power", "C 100 80", "D 100 60"],
+            vec![100, 80, 20, 0],
+        );
+        msg.targeting = Some(Targeting::LowestHp);
+        let mut rng = rules::seeded_rng(42);
+        assert_eq!(rules::select_target(&ruleset::Classic, &msg, &mut rng), 2);
+        msg.targeting = Some(Targeting::NextAlive);
+        assert_eq!(rules::select_target(&ruleset::Classic, &msg, &mut rng), 1);
+        msg.targeting = Some(Targeting::Random);
+        for _ in 0..20 {
+            let t = rules::select_target(&ruleset::Classic, &msg, &mut rng);
+            assert!(t == 1 || t == 2);
+        }
+        // the player targeting overrides the configured one
+        msg.turn = 1;
+        assert_eq!(rules::select_target(&ruleset::Classic, &msg, &mut rng), 0);
+        msg.turn = 0;
+        msg.targeting = Some(Targeting::Chosen);
+        assert_eq!(rules::parse_target(&msg, "c"), Some(2));
+        assert_eq!(rules::parse_target(&msg, "2"), Some(1));
+        assert_eq!(rules::parse_target(&msg, "D"), None);
+        assert_eq!(rules::parse_target(&msg, "A"), None);
+        msg.target = Some(2);
+        assert_eq!(rules::select_target(&ruleset::Classic, &msg, &mut rng), 2);
+        let mut log = EventLog::default();
+        rules::ask_target(&msg, &mut log).unwrap();
+        match log.actions[0] {
+            ClientAction::AskTarget { ref targets, .. } => assert_eq!(targets, &["B", "C"]),
+            ref a => panic!("{:?}", a),
+        }
+    }
+
+    #[test]
+    fn interactive_actions_test() {
+        let mut msg = battle(&["A 100 100", "B 100 90"], vec![100, 50]);
+        msg.turn = 1;
+        msg.action = Some(TurnAction::Heal);
+        let mut rng = rules::seeded_rng(42);
+        let mut log = EventLog::default();
+        let result = rules::battle_turn(&ruleset::Classic, &msg, &mut rng, &mut log).unwrap();
+        assert_eq!(result.hp, vec![100, 60]);
+        assert_eq!(result.next_turn, 0);
+        match log.actions[0] {
+            ClientAction::Heal { hp, .. } => assert_eq!(hp, 60),
+            ref a => panic!("{:?}", a),
+        }
+        msg.action = Some(TurnAction::Defend);
+        let result = rules::battle_turn(&ruleset::Classic, &msg, &mut rng, &mut log).unwrap();
+        assert_eq!(result.defending, vec![false, true]);
+        assert_eq!(result.next_turn, 0);
+        // the rule set picks who plays after a turn attacking nobody
+        let mut three = battle(&["A 100 100", "B 100 90", "C 100 80"], vec![100, 50, 80]);
+        three.turn = 1;
+        three.action = Some(TurnAction::Defend);
+        let other = rules::battle_turn(&ruleset::Classic, &three, &mut rng, &mut log).unwrap();
+        assert_eq!(other.next_turn, 2);
+        let other = rules::battle_turn(&Backwards, &three, &mut rng, &mut log).unwrap();
+        assert_eq!(other.next_turn, 0);
+        // a defending player takes half the damage
+        msg.turn = 0;
+        msg.action = Some(TurnAction::Attack(None));
+        msg.attacks = vec![Attack::new("Standard", 0, 100, 1, 2)];
+        msg.defending = result.defending;
+        let result = rules::battle_turn(&ruleset::Classic, &msg, &mut rng, &mut log).unwrap();
+        assert_eq!(result.hp, vec![100, 25]);
+        assert_eq!(result.defending, vec![false, true]);
+    }
+
+    #[test]
+    fn team_battle_test() {
+        let mut msg = battle(
+            &["A 100 100 @red", "B 100 60 @red", "C 100 80 @blue lowest-hp"],
+            vec![100, 10, 50],
+        );
+        msg.attacks = vec![Attack::new("Standard", 0, 100, 1, 1)];
+        msg.targeting = Some(Targeting::LowestHp);
+        let mut rng = rules::seeded_rng(42);
+        // allies are never attacked
+        assert_eq!(rules::targets(&msg.info, &msg.hp, 0), vec![2]);
+        assert_eq!(rules::select_target(&ruleset::Classic, &msg, &mut rng), 2);
+        assert_eq!(rules::parse_target(&msg, "B"), None);
+        msg.turn = 2;
+        assert_eq!(rules::select_target(&ruleset::Classic, &msg, &mut rng), 1);
+        // a rule set picking an ally attacks the next enemy alive instead
+        msg.turn = 0;
+        msg.targeting = None;
+        assert_eq!(rules::select_target(&Traitor, &msg, &mut rng), 2);
+        msg.targeting = Some(Targeting::LowestHp);
+        // the team wins when no enemy is left
+        msg.turn = 0;
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp, vec![100, 10, 0]);
+        assert_eq!(outcome.result.winner, Some((0, 100)));
+        let mut log = EventLog::default();
+        rules::battle_over(0, &msg.info, &outcome.result.hp, &mut log).unwrap();
+        match log.actions[0] {
+            ClientAction::Winner {
+                ref team,
+                ref survivors,
+                ..
+            } => {
+                assert_eq!(team, &Some(String::from("red")));
+                assert_eq!(survivors, &[(String::from("A"), 100), (String::from("B"), 10)]);
+            }
+            ref a => panic!("{:?}", a),
+        }
+    }
+
+    #[test]
+    fn turn_order_test() {
+        use initiative;
+        let info: Vec<PlayerInfo> = vec![
+            "A 100 100".parse().unwrap(),
+            "B 100 60 speed=30".parse().unwrap(),
+            "C 100 80".parse().unwrap(),
+        ];
+        let hp = vec![100, 100, 100];
+        let mut rng = rules::seeded_rng(42);
+        let mut schedule = Schedule::default();
+        let order = Some(TurnOrder::Initiative);
+        let mut round: Vec<usize> = (0..3)
+            .map(|_| initiative::next_turn(order, &info, &hp, &mut schedule, &mut rng).unwrap())
+            .collect();
+        assert_eq!(round[0], 1);
+        round.sort();
+        assert_eq!(round, vec![0, 1, 2]);
+        // knocked out players leave the round
+        let hp = vec![100, 100, 0];
+        for _ in 0..4 {
+            let next = initiative::next_turn(order, &info, &hp, &mut schedule, &mut rng);
+            assert_ne!(next, Some(2));
+        }
+        // in atb order the fastest player acts three times as often
+        let hp = vec![100, 100, 100];
+        let mut schedule = Schedule::default();
+        let mut turns = vec![0; 3];
+        for _ in 0..50 {
+            let next = initiative::next_turn(Some(TurnOrder::Atb), &info, &hp, &mut schedule, &mut rng);
+            turns[next.unwrap()] += 1;
+        }
+        assert_eq!(turns, vec![10, 30, 10]);
+        assert_eq!(initiative::next_turn(None, &info, &hp, &mut schedule, &mut rng), None);
+    }
+
+    #[test]
+    fn attributes_test() {
+        let info: Vec<PlayerInfo> = vec![
+            "A 100 100 crit=30 regen=20 max_hp=110".parse().unwrap(),
+            "B 100 60 evasion=10 defense=30".parse().unwrap(),
+        ];
+        let attacks = classic_attacks();
+        // evasion grows Miss and crit grows Critical
+        assert_eq!(rules::attribute_dice(&attacks, 20, &info[0], &info[1]), 0);
+        assert_eq!(rules::attribute_dice(&attacks, 80, &info[0], &info[1]), 96);
+        assert_eq!(rules::attribute_dice(&attacks, 50, &info[0], &info[1]), 50);
+        assert_eq!(rules::attribute_dice(&attacks, 80, &info[1], &info[0]), 80);
+        let msg = BattleTurnMsg::new(vec![Attack::new("Standard", 0, 100, 1, 1)], info, vec![95, 100]);
+        let mut rng = rules::seeded_rng(42);
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        // regeneration up to the max HP and defense taking away damage
+        assert_eq!(outcome.result.hp, vec![110, 30]);
+        // huge regeneration and healing stop at the max HP
+        let max = u64::max_value();
+        let mut msg = battle(&["A 100 10 regen=18446744073709551615", "B 100 10"], vec![90, 100]);
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp[0], 100);
+        msg.info[0] = PlayerInfo::new("A", max, 10);
+        msg.hp = vec![max - 1, 100];
+        msg.action = Some(TurnAction::Heal);
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp[0], max);
+    }
+
+    #[test]
+    fn status_effects_test() {
+        let effect = |kind, amount, turns| Effect { kind, amount, turns };
+        let mut poison = Attack::new("Standard", 0, 100, 1, 1);
+        poison.effect = Some(effect(EffectKind::Poison, 5, 2));
+        let mut msg = battle(&["A 100 20", "B 100 10"], vec![100, 100]);
+        msg.attacks = vec![poison];
+        let mut rng = rules::seeded_rng(42);
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp, vec![100, 80]);
+        assert_eq!(outcome.result.effects[1], vec![effect(EffectKind::Poison, 5, 2)]);
+        assert_eq!(outcome.effects.len(), 1);
+        // poison adds up, buffs keep the strongest, both refresh the duration
+        let mut on_b = outcome.result.effects[1].clone();
+        effects::apply(&mut on_b, &effect(EffectKind::Poison, 5, 1));
+        effects::apply(&mut on_b, &effect(EffectKind::Buff, 3, 1));
+        effects::apply(&mut on_b, &effect(EffectKind::Buff, 2, 3));
+        assert_eq!(on_b, vec![effect(EffectKind::Poison, 10, 2), effect(EffectKind::Buff, 3, 3)]);
+        // a stunned player takes the poison and loses the turn
+        msg.turn = 1;
+        msg.number = 2;
+        msg.hp = outcome.result.hp;
+        msg.effects = vec![Vec::new(), vec![effect(EffectKind::Stun, 0, 1), effect(EffectKind::Poison, 5, 2)]];
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert!(!outcome.acted);
+        assert_eq!(outcome.result.hp, vec![100, 75]);
+        assert_eq!(outcome.result.effects[1], vec![effect(EffectKind::Poison, 5, 1)]);
+        assert_eq!(outcome.ticks.len(), 3);
+        // shields absorb the damage until they break
+        msg.turn = 0;
+        msg.attacks[0].effect = None;
+        msg.effects = vec![Vec::new(), vec![effect(EffectKind::Shield, 15, 2)]];
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp, vec![100, 75]);
+        assert!(outcome.result.effects[1].is_empty());
+        match outcome.effects[..] {
+            [ClientAction::EffectExpired { effect: EffectKind::Shield, .. }] => (),
+            ref e => panic!("{:?}", e),
+        }
+        // huge buffs and poison stacks don't overflow
+        let max = u64::max_value();
+        let mut on_a = vec![effect(EffectKind::Poison, max, 1)];
+        effects::apply(&mut on_a, &effect(EffectKind::Poison, 5, 1));
+        assert_eq!(on_a, vec![effect(EffectKind::Poison, max, 1)]);
+        msg.info[0].power = max;
+        msg.effects = vec![vec![effect(EffectKind::Buff, 10, 1)], Vec::new()];
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp[1], 0);
+        // a stunned player isn't asked for an action
+        assert!(effects::stunned(&[Vec::new(), vec![effect(EffectKind::Stun, 0, 1)]], 1));
+        assert!(!effects::stunned(&[Vec::new(), vec![effect(EffectKind::Shield, 5, 1)]], 1));
+        assert!(!effects::stunned(&[], 1));
+        // effects last at least a turn
+        let mut stunning = classic_attacks();
+        stunning[1].effect = Some(effect(EffectKind::Stun, 0, 0));
+        assert!(rules::check_attacks(&stunning).is_err());
+        stunning[1].effect = Some(effect(EffectKind::Stun, 0, 1));
+        assert!(rules::check_attacks(&stunning).is_ok());
+    }
+
+    #[test]
+    fn abilities_test() {
+        let mut msg = battle(
+            &["A 100 30 mana=20 +heavy-strike", "B 100 10 mana=30 +mend"],
+            vec![100, 100],
+        );
+        msg.abilities = classic_abilities();
+        let mut rng = rules::seeded_rng(42);
+        // automatic players strike hard when they can
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert!(outcome.ability.is_some());
+        assert_eq!(outcome.result.hp, vec![100, 80]);
+        assert_eq!(outcome.result.mana, vec![10, 30]);
+        assert_eq!(outcome.result.cooldowns[0], vec![2, 0, 0]);
+        // and attack while it cools down
+        msg.number = 3;
+        msg.mana = outcome.result.mana;
+        msg.cooldowns = outcome.result.cooldowns;
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert!(outcome.ability.is_none());
+        assert_eq!(outcome.result.mana[0], 12);
+        // below half of the max HP they heal
+        msg.turn = 1;
+        msg.hp = vec![100, 40];
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp, vec![100, 65]);
+        assert_eq!(outcome.result.mana[1], 15);
+        // an ability the player doesn't have is an attack
+        msg.action = Some(TurnAction::Ability(String::from("whirlwind")));
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert!(outcome.ability.is_none());
+        assert!(rules::check_abilities(&classic_abilities(), &classic_classes()).is_ok());
+        assert!(rules::check_abilities(&[], &classic_classes()).is_err());
+        // mana comes back up to the pool without overflowing
+        let mut rich: PlayerInfo = "R 100 10".parse().unwrap();
+        rich.attributes.mana = u64::max_value();
+        let mut mana = u64::max_value() - 1;
+        abilities::recover(&rich, &mut mana, &mut []);
+        assert_eq!(mana, u64::max_value());
+    }
+
+    #[test]
+    fn items_test() {
+        // equipment adds it's stats when entering the battle
+        let knight = equip(&classic_items(), "D 100 10 *sword *armor".parse().unwrap()).unwrap();
+        assert_eq!((knight.energy, knight.power), (110, 20));
+        assert_eq!(knight.attributes.defense, 3);
+        assert!(equip(&classic_items(), "D 100 10 *wand".parse().unwrap()).is_err());
+        // and stops at the largest stats
+        let max = u64::max_value();
+        let mut giant = PlayerInfo::new("G", max, max);
+        giant.items = vec![String::from("sword"), String::from("armor"), String::from("boots")];
+        giant.attributes.defense = max;
+        giant.attributes.speed = Some(max);
+        let giant = equip(&classic_items(), giant).unwrap();
+        assert_eq!((giant.energy, giant.power), (max, max));
+        assert_eq!((giant.attributes.defense, giant.attributes.speed), (max, Some(max)));
+        let mut msg = battle(&["A 100 10 *bomb *potion", "B 100 10", "C 100 10"], vec![100, 100, 100]);
+        msg.action = Some(TurnAction::Item(String::from("bomb")));
+        msg.items = classic_items();
+        let mut rng = rules::seeded_rng(42);
+        // a bomb hits every enemy and is gone
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert!(outcome.ability.is_some());
+        assert_eq!(outcome.result.hp, vec![100, 80, 80]);
+        assert_eq!(outcome.result.inventory[0], vec![String::from("potion")]);
+        // an item the player doesn't carry anymore is an attack
+        msg.inventory = outcome.result.inventory;
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert!(outcome.ability.is_none());
+        // below a quarter of the max HP automatic players drink a potion
+        msg.action = None;
+        msg.hp = vec![20, 100, 100];
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.hp, vec![50, 100, 100]);
+        assert!(outcome.result.inventory[0].is_empty());
+        // a huge HP doesn't overflow the potion check
+        msg.hp = vec![max, 100, 100];
+        msg.info[0].energy = max;
+        msg.inventory = vec![vec![String::from("potion")], Vec::new(), Vec::new()];
+        let outcome = rules::resolve_turn(&ruleset::Classic, &msg, &mut rng).unwrap();
+        assert_eq!(outcome.result.inventory[0], vec![String::from("potion")]);
+        let abilities = classic_abilities();
+        assert!(rules::check_items(&classic_items(), &abilities, &[vec![String::from("potion")]]).is_ok());
+        assert!(rules::check_items(&classic_items(), &abilities, &[vec![String::from("wand")]]).is_err());
+        let mut broken = classic_items();
+        broken[0].heal = 0;
+        assert!(rules::check_items(&broken, &abilities, &[]).is_err());
+        // an item named like an ability could never be used
+        let mut broken = classic_items();
+        broken[0].name = abilities[0].name.to_uppercase();
+        assert!(rules::check_items(&broken, &abilities, &[]).is_err());
+    }
+
+    #[test]
+    fn script_rules_test() {
+        use ruleset::BattleRules;
+        use script::ScriptRules;
+        let info: Vec<PlayerInfo> = vec![
+            "A 100 100".parse().unwrap(),
+            "B 100 60".parse().unwrap(),
+            "C 80 60".parse().unwrap(),
+        ];
+        let script = ScriptRules::new(
+            "last",
+            "fn select_target(hp, power, turn, energy, names) {
+                 if turn == 2 { return 0; }
+                 if names[2] == \"C\" && energy[2] == 80 { return 2; }
+                 return 1;
+             }
+             fn damage(attack, power, name, energy) {
+                 if attack == \"Miss\" { return 0; }
+                 return power + roll() - roll();
+             }",
+        ).unwrap();
+        assert_eq!(script.name(), "last");
+        assert_eq!(script.select_target(&info, &[100, 80, 20], 0), 2);
+        assert_eq!(script.select_target(&info, &[100, 80, 20], 2), 0);
+        // rolls come from the dice of the game
+        let lucky = Attack::new("Lucky", 70, 96, 2, 5);
+        script.start_turn(&mut rules::seeded_rng(42));
+        let damage = script.damage(&lucky, &info[0]);
+        script.start_turn(&mut rules::seeded_rng(42));
+        assert_eq!(damage, script.damage(&lucky, &info[0]));
+        assert!(damage < 200);
+        assert_eq!(script.damage(&Attack::new("Miss", 0, 15, 0, 1), &info[0]), 0);
+        assert!(script.finish_turn().is_ok());
+        // missing hooks play the classic rules
+        assert_eq!(script.winner(&[100, 0, 0], 0), Some(0));
+        assert!(ScriptRules::new("broken", "fn select_target(").is_err());
+        let e = ruleset::load("script", None, Default::default()).err().unwrap();
+        assert!(e.to_string().contains("Rhai script"), "{}", e);
+        // hooks must take the arguments they are called with
+        assert!(ScriptRules::new("old", "fn damage(attack, power) { return power; }").is_err());
+        // endless hooks are stopped and fail the turn
+        let endless = ScriptRules::new("endless", "fn damage(attack, power, name, energy) { loop { } }").unwrap();
+        assert_eq!(endless.damage(&lucky, &info[0]), ruleset::Classic.damage(&lucky, &info[0]));
+        assert!(endless.finish_turn().is_err());
+        let deep = "fn next_turn(hp, turn, defender) { return next_turn(hp, turn, defender); }";
+        let deep = ScriptRules::new("deep", deep).unwrap();
+        assert_eq!(deep.next_turn(&[100, 80, 20], 0, 1), 1);
+        assert!(deep.finish_turn().is_err());
+        // a hook failing on a turn fails the turn
+        let failing = ScriptRules::new(
+            "failing",
+            "fn select_target(hp, power, turn, energy, names) { if hp[1] == 0 { return hp[10]; } return 1; }",
+        ).unwrap();
+        assert_eq!(failing.select_target(&info, &[100, 0, 20], 0), 2);
+        assert!(failing.finish_turn().is_err());
+        assert!(failing.finish_turn().is_ok());
+        let msg = battle(&["A 100 100", "B 100 60", "C 80 60"], vec![100, 0, 20]);
+        let mut rng = rules::seeded_rng(42);
+        assert!(rules::resolve_turn(&failing, &msg, &mut rng).is_err());
+        let msg = battle(&["A 100 100", "B 100 60", "C 80 60"], vec![100, 60, 20]);
+        assert!(rules::resolve_turn(&failing, &msg, &mut rng).is_ok());
+    }
+
+    #[test]
+    fn wasm_rules_test() {
+        use wasm::{WasmLimits, WasmRules};
+        assert!(WasmRules::new("empty", b"", WasmLimits::default()).is_err());
+        // a module without any export can't play a turn
+        let empty_module = b"\0asm\x01\0\0\0";
+        assert!(WasmRules::new("nothing", empty_module, WasmLimits::default()).is_err());
+        let e = ruleset::load("wasm", None, WasmLimits::default()).err().unwrap();
+        assert!(e.to_string().contains(".wasm module"), "{}", e);
+        // a start function is refused instead of running outside a turn
+        //   (func) (start 0)
+        let start_module = b"\0asm\x01\0\0\0\x01\x04\x01`\x00\x00\x03\x02\x01\x00\x08\x01\x00\x0a\x04\x01\x02\x00\x0b";
+        let e = WasmRules::new("start", start_module, WasmLimits::default()).err().unwrap();
+        assert!(e.to_string().contains("start function"), "{}", e);
+        // hand assembled abi 2 modules, the exports of both are
+        //   (func (export "abi_version") (result i32) i32.const 2)
+        //   (func (export "alloc") (param i32) (result i32) i32.const 1024)
+        // and resolve_turn answers the result of the data segment when
+        // the memory can grow a page
+        //   (memory (export "memory") 1)
+        //   (data (i32.const 0) "{\"hp\":[100,0],...}")
+        //   (func (export "resolve_turn") (param i32 i32 i32) (result i64)
+        //     (if (result i64) (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+        //       (then i64.const 0) (else i64.const 45)))
+        let grow_module: &[u8] = b"\x00asm\x01\x00\x00\x00\x01\x11\x03`\x00\x01\x7f`\x01\x7f\x01\x7f`\x03\x7f\x7f\x7f\x01~\x03\x04\x03\x00\x01\x02\x05\x03\x01\x00\x01\x07/\x04\x06memory\x02\x00\x0babi_version\x00\x00\x05alloc\x00\x01\x0cresolve_turn\x00\x02\x0a\x1e\x03\x04\x00A\x02\x0b\x05\x00A\x80\x08\x0b\x11\x00A\x01@\x00A\x7fF\x04~B\x00\x05B-\x0b\x0b\x0b3\x01\x00A\x00\x0b-{\"hp\":[100,0],\"next_turn\":0,\"winner\":[0,100]}";
+        // or loops forever
+        //   (func (export "resolve_turn") (param i32 i32 i32) (result i64)
+        //     (loop (br 0)) i64.const 0)
+        let loop_module: &[u8] = b"\x00asm\x01\x00\x00\x00\x01\x11\x03`\x00\x01\x7f`\x01\x7f\x01\x7f`\x03\x7f\x7f\x7f\x01~\x03\x04\x03\x00\x01\x02\x05\x03\x01\x00\x01\x07/\x04\x06memory\x02\x00\x0babi_version\x00\x00\x05alloc\x00\x01\x0cresolve_turn\x00\x02\x0a\x16\x03\x04\x00A\x02\x0b\x05\x00A\x80\x08\x0b\x09\x00\x03@\x0c\x00\x0bB\x00\x0b";
+        let msg = battle(&["A 100 100", "B 100 60"], vec![100, 60]);
+        let rules = WasmRules::new("grow", grow_module, WasmLimits::default()).unwrap();
+        let result = rules.resolve_turn(&msg, 99).unwrap();
+        assert_eq!(result.hp, vec![100, 0]);
+        assert_eq!(result.next_turn, 0);
+        assert_eq!(result.winner, Some((0, 100)));
+        // the rest of the battle carries on as the turn had it
+        let mut msg = msg;
+        msg.defending = vec![false, true];
+        msg.mana = vec![30, 10];
+        msg.inventory = vec![vec![String::from("Potion")], Vec::new()];
+        let result = rules.resolve_turn(&msg, 99).unwrap();
+        assert_eq!(result.defending, msg.defending);
+        assert_eq!(result.mana, msg.mana);
+        assert_eq!(result.inventory, msg.inventory);
+        // the memory can't grow past the limit, nor start above it
+        let capped = WasmLimits {
+            memory_pages: 1,
+            ..Default::default()
+        };
+        let rules = WasmRules::new("grow", grow_module, capped).unwrap();
+        assert!(rules.resolve_turn(&msg, 99).is_err());
+        let none = WasmLimits {
+            memory_pages: 0,
+            ..Default::default()
+        };
+        assert!(WasmRules::new("grow", grow_module, none).is_err());
+        // an endless turn burns all the fuel
+        let fuel = WasmLimits {
+            fuel: 10_000,
+            ..Default::default()
+        };
+        let rules = WasmRules::new("loop", loop_module, fuel).unwrap();
+        let e = rules.resolve_turn(&msg, 99).unwrap_err();
+        assert!(e.to_string().contains("out of fuel"), "{}", e);
+    }
+
+    #[test]
+    fn simulation_threads_test() {
+        let info: Vec<PlayerInfo> = vec![
+            "A 100 100".parse().unwrap(),
+            "B 100 60".parse().unwrap(),
+        ];
+        let settings = simulation::BattleSettings {
+            attacks: classic_attacks(),
+            ..Default::default()
+        };
+        let classic = ruleset::by_name(ruleset::DEFAULT_RULES).unwrap();
+        let one = simulation::simulate(classic.clone(), &settings, &info, 200, 7, 1).unwrap();
+        let many = simulation::simulate(classic, &settings, &info, 200, 7, 4).unwrap();
+        assert_eq!(one.runs, 200);
+        assert_eq!(one.wins, many.wins);
+        assert_eq!(one.turns, many.turns);
+        assert_eq!(one.hp_distribution, many.hp_distribution);
+        assert_eq!(one.wins.iter().sum::<u64>() + one.draws, 200);
+        assert_eq!(one.attacks.values().sum::<u64>() + one.no_attack, one.turns);
+        // winners at full HP of the largest energy land on the last bucket
+        let giant: Vec<PlayerInfo> = vec![
+            "A 18446744073709551615 100".parse().unwrap(),
+            "B 10 1".parse().unwrap(),
+        ];
+        let classic = ruleset::by_name(ruleset::DEFAULT_RULES).unwrap();
+        let report = simulation::simulate(classic, &settings, &giant, 20, 7, 1).unwrap();
+        assert_eq!(report.wins[0], 20);
+        assert_eq!(report.hp_distribution.last(), Some(&20));
     }
 }
@@ -1,60 +1,185 @@
-use actix::Addr;
-use core::{ClientAction, PlayerInfo, AttackType, TurnResultMsg};
-use engine_io::EnginePipeIo;
+use core::{
+    check_player_abilities, Ability, Attack, Attributes, BattleTurnMsg, CharacterClass,
+    ClientAction, Effect, Item, ItemKind, PlayerInfo, ReplayEvent, Schedule, Targeting, TurnAction, TurnResultMsg,
+};
 use failure::Error;
-use rand::{self, Rng};
+use abilities;
+use effects;
+use initiative;
+use items;
+use rand::prng::ChaChaRng;
+use rand::{self, Rng, SeedableRng};
+use ruleset::{BattleRules, Classic};
+use sink::BattleEventSink;
+use std::cmp::Reverse;
 use std::slice::Iter;
 
+/// Healing recovers this fraction of the player energy
+pub const HEAL_DIVISOR: u64 = 10;
+
+/// Dice generator, an explicit algorithm is used instead of StdRng
+/// as the same seed must give the same rolls across releases
+pub type DiceRng = ChaChaRng;
+
+/// Generates a new seed for a battle not seeded by configuration
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+/// Dice generator initialized from a battle seed
+pub fn seeded_rng(seed: u64) -> DiceRng {
+    DiceRng::seed_from_u64(seed)
+}
+
+/// Seed of the next game of a series drawn from the dice of the last one,
+/// the dice start over from it so the game replays alone from it's seed
+pub fn next_game(seed: &mut u64, rng: &mut DiceRng) {
+    *seed = rng.gen();
+    *rng = seeded_rng(*seed);
+}
+
 /// Generates a new random integer between 0 and 100
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn roll_dice() -> u8 {
-    rand::thread_rng().gen_range(0, 100)
+pub fn roll_dice<R: Rng>(rng: &mut R) -> u8 {
+    rng.gen_range(0, 100)
 }
 
-/// If the attack is missed the attack damage is 0
+/// Attack of the table fired by a dice roll, dice falling outside every
+/// range fire no attack
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn rule_miss(_power: u64) -> u64 {
-    0
+pub fn find_attack(attacks: &[Attack], dice: u8) -> Option<&Attack> {
+    attacks.iter().find(|a| dice >= a.from && dice < a.to)
 }
 
-/// If the attack is standard the attack damage is 1/3 the power
-#[cfg_attr(feature = "flame_it", flame)]
-pub fn rule_standard(power: u64) -> u64 {
-    power / 3
+/// Validates an attack table, ranges must be inside the dice rolls and
+/// must not overlap
+pub fn check_attacks(attacks: &[Attack]) -> Result<(), Error> {
+    for (i, a) in attacks.iter().enumerate() {
+        if a.from >= a.to || a.to > 100 {
+            return Err(format_err!("Attack {} range {}..{} is invalid", a.name, a.from, a.to));
+        }
+        if a.div == 0 || a.base == 0 {
+            return Err(format_err!("Attack {} divisor is 0", a.name));
+        }
+        if a.effect.as_ref().map_or(false, |e| e.turns == 0) {
+            return Err(format_err!("Attack {} effect lasts 0 turns", a.name));
+        }
+        if let Some(b) = attacks[..i].iter().find(|b| a.from < b.to && b.from < a.to) {
+            return Err(format_err!("Attack {} range overlaps {}", a.name, b.name));
+        }
+    }
+    Ok(())
 }
 
-/// If the attack is lucky the attack damage is 20% more than standard
-#[cfg_attr(feature = "flame_it", flame)]
-pub fn rule_lucky(power: u64) -> u64 {
-    let std = rule_standard(power);
-    std + std / 5
+/// Checks the class catalog, every class needs an unique name a player
+/// line can hold, letters, `-` and `_`, and some energy
+pub fn check_classes(classes: &[CharacterClass]) -> Result<(), Error> {
+    for (i, c) in classes.iter().enumerate() {
+        let word = c.name.chars().all(|ch| ch.is_alphabetic() || ch == '-' || ch == '_');
+        if c.name.is_empty() || !word {
+            return Err(format_err!("Class name \"{}\" is invalid", c.name));
+        }
+        if c.energy == 0 {
+            return Err(format_err!("Class {} has no energy", c.name));
+        }
+        if c.attributes.evasion > 100 || c.attributes.crit > 100 {
+            return Err(format_err!("Class {} evasion or crit is over 100", c.name));
+        }
+        if classes[..i].iter().any(|d| d.name.eq_ignore_ascii_case(&c.name)) {
+            return Err(format_err!("Class {} is repeated", c.name));
+        }
+    }
+    Ok(())
 }
 
-/// If the attack is critical the attack damage is two times the standard
-#[cfg_attr(feature = "flame_it", flame)]
-pub fn rule_critical(power: u64) -> u64 {
-    2 * rule_standard(power)
+/// Checks the ability catalog, every ability needs an unique name a player
+/// line can hold and every ability of the classes must be on it
+pub fn check_abilities(abilities: &[Ability], classes: &[CharacterClass]) -> Result<(), Error> {
+    for (i, a) in abilities.iter().enumerate() {
+        let word = a.name.chars().all(|ch| ch.is_alphabetic() || ch == '-' || ch == '_');
+        if a.name.is_empty() || !word {
+            return Err(format_err!("Ability name \"{}\" is invalid", a.name));
+        }
+        if a.div == 0 {
+            return Err(format_err!("Ability {} divisor is 0", a.name));
+        }
+        if a.effect.as_ref().map_or(false, |e| e.turns == 0) {
+            return Err(format_err!("Ability {} effect lasts 0 turns", a.name));
+        }
+        if abilities[..i].iter().any(|b| b.name.eq_ignore_ascii_case(&a.name)) {
+            return Err(format_err!("Ability {} is repeated", a.name));
+        }
+    }
+    for c in classes {
+        let mut class = PlayerInfo::new(&c.name, c.energy, c.power);
+        class.abilities = c.abilities.clone();
+        check_player_abilities(abilities, class)
+            .map_err(|e| format_err!("Class {}: {}", c.name, e))?;
+    }
+    Ok(())
 }
 
-// Somewhat generic and parametrized ranges defining which cluster
-// to which rule one to one mapping
-#[cfg_attr(feature = "flame_it", flame)]
-pub fn get_cluster_reducer(range_params: Iter<'_, u8>, dice: u8) -> (AttackType, Box<Fn(u64) -> u64>) {
-    let mut lower = 0u8;
-    for (i, upper) in range_params.enumerate() {
-        if dice >= lower && dice < *upper {
-            match i {
-                0 => return (AttackType::Miss,Box::new(rule_miss)),
-                1 => return (AttackType::Standard,Box::new(rule_standard)),
-                2 => return (AttackType::Lucky, Box::new(rule_lucky)),
-                3 => return (AttackType::Critical, Box::new(rule_critical)),
-                _ => return (AttackType::Undefined, Box::new(|_| 0)),
-            }
-        } else {
-            lower = *upper;
+/// Checks the item catalog, every item needs an unique name a player line
+/// can hold, that no ability takes as players answer both by name, and to
+/// do what it's kind does, a potion heals or helps, a bomb hurts and
+/// equipment only adds stats. Every item of the loadouts must be on it
+pub fn check_items(items: &[Item], abilities: &[Ability], loadouts: &[Vec<String>]) -> Result<(), Error> {
+    for (i, item) in items.iter().enumerate() {
+        let word = item.name.chars().all(|ch| ch.is_alphabetic() || ch == '-' || ch == '_');
+        if item.name.is_empty() || !word {
+            return Err(format_err!("Item name \"{}\" is invalid", item.name));
+        }
+        if items[..i].iter().any(|other| other.name.eq_ignore_ascii_case(&item.name)) {
+            return Err(format_err!("Item {} is repeated", item.name));
+        }
+        if abilities.iter().any(|a| a.name.eq_ignore_ascii_case(&item.name)) {
+            return Err(format_err!("Item {} is named like an ability", item.name));
+        }
+        let harmful = item.effect.as_ref().map(|e| e.kind.harmful());
+        let stats = item.energy > 0 || item.power > 0 || item.attributes != Attributes::default();
+        let valid = match item.kind {
+            ItemKind::Potion => item.heal > 0 || harmful == Some(false),
+            ItemKind::Bomb => item.damage > 0 || harmful == Some(true),
+            ItemKind::Equipment => stats && item.heal == 0 && item.damage == 0 && harmful.is_none(),
+        };
+        if !valid {
+            let needs = match item.kind {
+                ItemKind::Potion => "a potion needs heal or a helpful effect",
+                ItemKind::Bomb => "a bomb needs damage or a harmful effect",
+                ItemKind::Equipment => "equipment needs stats and no heal, damage or effect",
+            };
+            return Err(format_err!("Item {}: {}", item.name, needs));
         }
+        if item.effect.as_ref().map_or(false, |e| e.turns == 0) {
+            return Err(format_err!("Item {}: effect lasts 0 turns", item.name));
+        }
+        if item.attributes.evasion > 100 || item.attributes.crit > 100 {
+            return Err(format_err!("Item {}: evasion and crit go up to 100", item.name));
+        }
+    }
+    for (seat, loadout) in loadouts.iter().enumerate() {
+        if let Some(name) = loadout
+            .iter()
+            .find(|name| !items.iter().any(|i| i.name.eq_ignore_ascii_case(name)))
+        {
+            return Err(format_err!("Loadout of seat {}: unknown item {}", seat + 1, name));
+        }
+    }
+    Ok(())
+}
+
+/// Dice roll shifted by the attributes, the evasion of the defender grows
+/// the lowest range of the attack table, usually Miss, and the crit of the
+/// attacker grows the highest one, usually Critical
+pub fn attribute_dice(attacks: &[Attack], dice: u8, attacker: &PlayerInfo, defender: &PlayerInfo) -> u8 {
+    let (evasion, crit) = (defender.attributes.evasion, attacker.attributes.crit);
+    let lowest = attacks.iter().min_by_key(|a| a.from);
+    let highest = attacks.iter().max_by_key(|a| a.to);
+    match (lowest, highest) {
+        (Some(low), _) if evasion > 0 && dice < low.to.saturating_add(evasion) => low.from,
+        (_, Some(high)) if crit > 0 && dice >= high.from.saturating_sub(crit) => high.from,
+        _ => dice,
     }
-    (AttackType::Undefined, Box::new(|_| 0))
 }
 
 #[cfg_attr(feature = "flame_it", flame)]
@@ -76,60 +201,558 @@ pub fn is_winner(hp: Iter<'_, u64>, turn: usize) -> bool {
     true
 }
 
+/// Targeting of the player on turn, it's own or the configured one
+pub fn targeting(msg: &BattleTurnMsg) -> Option<Targeting> {
+    msg.info[msg.turn].targeting.or(msg.targeting)
+}
+
+/// Whether two players fight on the same team
+pub fn allies(info: &[PlayerInfo], a: usize, b: usize) -> bool {
+    match (info.get(a).and_then(|i| i.team.as_ref()), info.get(b).and_then(|i| i.team.as_ref())) {
+        (Some(ta), Some(tb)) => a != b && ta == tb,
+        _ => false,
+    }
+}
+
+/// HP as seen by the player on turn, it's allies look knocked out so
+/// rule sets never target them and a team wins when no enemy is left
+pub fn enemy_hp(info: &[PlayerInfo], hp: &[u64], turn: usize) -> Vec<u64> {
+    hp.iter()
+        .enumerate()
+        .map(|(i, h)| if allies(info, turn, i) { 0 } else { *h })
+        .collect()
+}
+
+/// Players the attacker can target, every enemy alive
+pub fn targets(info: &[PlayerInfo], hp: &[u64], turn: usize) -> Vec<usize> {
+    (0..hp.len())
+        .filter(|&i| i != turn && hp[i] > 0 && !allies(info, turn, i))
+        .collect()
+}
+
+/// Target of the attacker by it's targeting, the rule set decides when
+/// there is no targeting or a chosen target is missing
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn battle_turn(
-    range: &[u8],
-    info: &[PlayerInfo],
-    hp: &[u64],
-    turn: usize,
-    io: Addr<EnginePipeIo>,
-) -> Result<TurnResultMsg, Error> {
-    let mut p2 = if turn == info.len() - 1 { 0 } else { turn + 1 };
-    while hp[p2] <= 0 { // for more than 2 players
-        p2 = if p2 == info.len() - 1 { 0 } else { p2 + 1 };
-    }
-    let mut out_hp: Vec<u64> = hp.iter().cloned().collect();
-    // Send message asynchronously to Game IO Actor mailbox (stdout)
-    let _ = io.do_send(ClientAction::PlayerAction(
-        info[turn].name.clone(),
-        info[p2].name.clone(),
-    ));
-    let dice = roll_dice();
-    let (attack, reducer) = get_cluster_reducer(range.iter(), dice);
-    let dmg = &reducer(info[turn].power);
-    let new_hp = process_dmg(&hp[p2], &dmg);
-    match attack {
-        AttackType::Undefined => (),
-        _ => {
-                // Send message asynchronously to Game IO Actor mailbox (stdout)
-                let _ = io.do_send(ClientAction::AttackResult(attack, *dmg));
-        }
-    }
-    out_hp[p2] = new_hp;
-    while out_hp[p2] <= 0 { // for more than 2 players next_turn
-        p2 = if p2 == info.len() - 1 { 0 } else { p2 + 1 };
-    }    
-    let winner = if is_winner(out_hp.iter(), turn) {
-        Some((turn, out_hp[turn]))
+pub fn select_target<R: Rng>(rule_set: &BattleRules, msg: &BattleTurnMsg, rng: &mut R) -> usize {
+    let (info, turn) = (&msg.info, msg.turn);
+    let hp = &enemy_hp(info, &msg.hp, turn);
+    let targets = targets(info, hp, turn);
+    if let Some(t) = msg.target.filter(|t| targets.contains(t)) {
+        return t;
+    }
+    let target = match (targeting(msg), targets.is_empty()) {
+        (_, true) | (None, _) | (Some(Targeting::Chosen), _) => None,
+        (Some(Targeting::NextAlive), _) => Some(Classic.select_target(info, hp, turn)),
+        (Some(Targeting::Random), _) => Some(targets[rng.gen_range(0, targets.len())]),
+        (Some(Targeting::LowestHp), _) => targets.iter().cloned().min_by_key(|&i| hp[i]),
+        (Some(Targeting::HighestPower), _) => targets
+            .iter()
+            .cloned()
+            .max_by_key(|&i| (info[i].power, Reverse(i))),
+    };
+    let target = target.unwrap_or_else(|| rule_set.select_target(info, hp, turn));
+    // the rule set may still pick an ally or a player knocked out, the
+    // next enemy alive is attacked instead
+    if targets.is_empty() || targets.contains(&target) {
+        target
+    } else {
+        Classic.select_target(info, hp, turn)
+    }
+}
+
+/// Target picked by the attacker by name or by it's number, only players
+/// it can target are accepted
+pub fn parse_target(msg: &BattleTurnMsg, choice: &str) -> Option<usize> {
+    let choice = choice.trim();
+    targets(&msg.info, &msg.hp, msg.turn).into_iter().find(|&i| {
+        msg.info[i].name.eq_ignore_ascii_case(choice) || (i + 1).to_string() == choice
+    })
+}
+
+/// Everything that happened on a turn
+#[derive(Debug, Clone)]
+pub struct TurnOutcome {
+    pub attacker: usize,
+    pub defender: usize,
+    pub dice: u8,
+    pub attack: Option<Attack>,
+    pub damage: u64,
+    /// Action of the attacker, an automatic attack when None
+    pub action: Option<TurnAction>,
+    /// Whether the player on turn acted, a stunned or knocked out one doesn't
+    pub acted: bool,
+    /// Status effects acting at the start of the turn
+    pub ticks: Vec<ClientAction>,
+    /// Status effects applied, or shields broken, by the attack
+    pub effects: Vec<ClientAction>,
+    /// The ability used, or the item consumed, instead of the attack
+    pub ability: Option<ClientAction>,
+    pub result: TurnResultMsg,
+}
+
+/// Plays a turn straight on the rules without any IO, used by the bpm
+/// actor and by headless simulations
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn resolve_turn<R: Rng>(
+    rule_set: &BattleRules,
+    msg: &BattleTurnMsg,
+    rng: &mut R,
+) -> Result<TurnOutcome, Error> {
+    rule_set.start_turn(rng);
+    let outcome = play(rule_set, msg, rng);
+    rule_set.finish_turn()?;
+    let mut outcome = outcome?;
+    // the turn order picks the next player, unless the rule set decides
+    let mut schedule = msg.schedule.clone();
+    let next = initiative::next_turn(msg.turn_order, &msg.info, &outcome.result.hp, &mut schedule, rng);
+    if let Some(next) = next {
+        outcome.result.next_turn = next;
+    }
+    outcome.result.schedule = schedule;
+    Ok(outcome)
+}
+
+// next player after a turn attacking nobody, the rule set decides as if
+// the next player alive had been attacked so the one on turn doesn't
+// strike back at itself
+fn pass_turn(rule_set: &BattleRules, info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+    rule_set.next_turn(hp, turn, Classic.select_target(info, hp, turn))
+}
+
+// the turn as played by the rule set
+fn play<R: Rng>(rule_set: &BattleRules, msg: &BattleTurnMsg, rng: &mut R) -> Result<TurnOutcome, Error> {
+    let (info, hp, turn) = (&msg.info, &msg.hp, msg.turn);
+    let dice = roll_dice(rng);
+    if let Some(result) = rule_set.play_turn(msg, dice) {
+        // the defender is the player losing HP, or the target of the
+        // rule set when the attack missed
+        let result = result?;
+        if result.hp.len() != hp.len() || result.next_turn >= hp.len() {
+            return Err(format_err!("Rule set {} played an invalid turn", rule_set.name()));
+        }
+        // allies of the attacker are never hurt
+        if (0..hp.len()).any(|i| allies(info, turn, i) && result.hp[i] < hp[i]) {
+            return Err(format_err!("Rule set {} attacked an ally of {}", rule_set.name(), info[turn].name));
+        }
+        let defender = (0..hp.len())
+            .find(|&i| result.hp[i] < hp[i])
+            .unwrap_or_else(|| rule_set.select_target(info, hp, turn));
+        return Ok(TurnOutcome {
+            attacker: turn,
+            defender,
+            dice,
+            attack: rule_set.resolve_attack(&msg.attacks, dice),
+            damage: hp[defender].saturating_sub(result.hp[defender]),
+            action: msg.action.clone(),
+            acted: true,
+            ticks: Vec::new(),
+            effects: Vec::new(),
+            ability: None,
+            result,
+        });
+    }
+    let (mana, cooldowns) = abilities::pools(msg);
+    let mut board = Board {
+        hp: hp.to_vec(),
+        // a player defends until it's next turn
+        defending: if msg.defending.len() == hp.len() {
+            msg.defending.clone()
+        } else {
+            vec![false; hp.len()]
+        },
+        effects: effects::of_players(&msg.effects, hp.len()),
+        mana,
+        cooldowns,
+        inventory: items::inventories(msg),
+    };
+    // regeneration comes before anything else on the turn of the player
+    let max_hp = info[turn].max_hp().max(hp[turn]);
+    board.hp[turn] = hp[turn].saturating_add(info[turn].attributes.regen).min(max_hp);
+    // then it's status effects act, mana comes back and cooldowns run down
+    let tick = effects::tick(&mut board.effects[turn], &mut board.hp[turn], msg.number, turn, &info[turn].name);
+    abilities::recover(&info[turn], &mut board.mana[turn], &mut board.cooldowns[turn]);
+    board.defending[turn] = false;
+    let acted = !tick.stunned && board.hp[turn] > 0;
+    let buffed;
+    let attacker = if tick.buff > 0 {
+        buffed = PlayerInfo {
+            power: info[turn].power.saturating_add(tick.buff),
+            ..info[turn].clone()
+        };
+        &buffed
+    } else {
+        &info[turn]
+    };
+    // a potion comes before an ability, and both before the attack
+    let item = if acted {
+        items::pick(msg, &board.hp, &board.inventory[turn])
+    } else {
+        None
+    };
+    let ability = if acted && item.is_none() {
+        abilities::pick(msg, &board.hp, board.mana[turn], &board.cooldowns[turn])
     } else {
         None
     };
-    Ok(TurnResultMsg {
-        hp: out_hp,
-        next_turn: p2,
-        winner,
+    let mut board = match (item, ability) {
+        (Some(index), _) => {
+            let outcome = use_item(rule_set, msg, rng, attacker, index, board);
+            return Ok(TurnOutcome { dice, ticks: tick.actions, ..outcome });
+        }
+        (None, Some(index)) => {
+            let outcome = use_ability(rule_set, msg, rng, attacker, index, board);
+            return Ok(TurnOutcome { dice, ticks: tick.actions, ..outcome });
+        }
+        (None, None) => board,
+    };
+    let attacking = acted && match msg.action {
+        Some(TurnAction::Defend) => {
+            board.defending[turn] = true;
+            false
+        }
+        Some(TurnAction::Heal) => {
+            // healing never goes above the player max HP
+            board.hp[turn] = board.hp[turn].saturating_add(info[turn].energy / HEAL_DIVISOR).min(max_hp);
+            false
+        }
+        _ => true,
+    };
+    if !attacking {
+        // nobody is attacked, the rule set picks who plays
+        let next_turn = pass_turn(rule_set, info, &board.hp, turn);
+        // poison may knock out the player on turn
+        let winner = last_standing(rule_set, info, &board.hp);
+        return Ok(TurnOutcome {
+            attacker: turn,
+            defender: turn,
+            dice,
+            attack: None,
+            damage: 0,
+            action: msg.action.clone(),
+            acted,
+            ticks: tick.actions,
+            effects: Vec::new(),
+            ability: None,
+            result: board.result(next_turn, winner),
+        });
+    }
+    let defender = select_target(rule_set, msg, rng);
+    let attack_dice = attribute_dice(&msg.attacks, dice, &info[turn], &info[defender]);
+    let attack = rule_set.resolve_attack(&msg.attacks, attack_dice);
+    let dmg = attack.as_ref().map_or(0, |a| rule_set.damage(a, attacker));
+    let mut applied = Vec::new();
+    let dmg = board.strike(info, defender, dmg, &mut applied);
+    if let Some(effect) = attack.as_ref().and_then(|a| a.effect.as_ref()) {
+        board.put_effect(info, turn, defender, dmg, effect, &mut applied);
+    }
+    let next_turn = rule_set.next_turn(&board.hp, turn, defender);
+    let winner = rule_set
+        .winner(&enemy_hp(info, &board.hp, turn), turn)
+        .map(|w| (w, board.hp[w]));
+    Ok(TurnOutcome {
+        attacker: turn,
+        defender,
+        dice,
+        attack,
+        damage: dmg,
+        action: msg.action.clone(),
+        acted,
+        ticks: tick.actions,
+        effects: applied,
+        ability: None,
+        result: board.result(next_turn, winner),
     })
 }
 
+// state of the players changing along a turn
+struct Board {
+    hp: Vec<u64>,
+    defending: Vec<bool>,
+    effects: Vec<Vec<Effect>>,
+    mana: Vec<u64>,
+    cooldowns: Vec<Vec<u64>>,
+    inventory: Vec<Vec<String>>,
+}
+
+impl Board {
+    // damage of a hit taken away by the defense of the defender, halved
+    // when it defends and absorbed by it's shields, returns the damage
+    // that made it to the HP
+    fn strike(&mut self, info: &[PlayerInfo], defender: usize, damage: u64, actions: &mut Vec<ClientAction>) -> u64 {
+        let mut dmg = damage.saturating_sub(info[defender].attributes.defense);
+        if self.defending[defender] {
+            dmg /= 2;
+        }
+        dmg = effects::absorb(&mut self.effects[defender], dmg, &info[defender].name, actions);
+        self.hp[defender] = process_dmg(&self.hp[defender], &dmg);
+        dmg
+    }
+
+    // harmful effects need a hit on a defender still standing, the others
+    // are put by the attacker on itself
+    fn put_effect(
+        &mut self,
+        info: &[PlayerInfo],
+        attacker: usize,
+        defender: usize,
+        damage: u64,
+        effect: &Effect,
+        actions: &mut Vec<ClientAction>,
+    ) {
+        let target = if effect.kind.harmful() { defender } else { attacker };
+        if effect.kind.harmful() && (damage == 0 || self.hp[defender] == 0) {
+            return;
+        }
+        effects::apply(&mut self.effects[target], effect);
+        actions.push(ClientAction::EffectApplied {
+            player_name: info[target].name.clone(),
+            effect: effect.kind,
+            amount: effect.amount,
+            turns: effect.turns,
+        });
+    }
+
+    fn result(self, next_turn: usize, winner: Option<(usize, u64)>) -> TurnResultMsg {
+        TurnResultMsg {
+            hp: self.hp,
+            next_turn,
+            winner,
+            defending: self.defending,
+            schedule: Schedule::default(),
+            effects: self.effects,
+            mana: self.mana,
+            cooldowns: self.cooldowns,
+            inventory: self.inventory,
+        }
+    }
+}
+
+// the player on turn uses an ability of the catalog instead of attacking
+fn use_ability<R: Rng>(
+    rule_set: &BattleRules,
+    msg: &BattleTurnMsg,
+    rng: &mut R,
+    attacker: &PlayerInfo,
+    index: usize,
+    mut board: Board,
+) -> TurnOutcome {
+    let turn = msg.turn;
+    let ability = &msg.abilities[index];
+    board.mana[turn] -= ability.cost;
+    if let Some(c) = board.cooldowns[turn].get_mut(index) {
+        *c = ability.cooldown;
+    }
+    let special = special(rule_set, msg, rng, attacker.power, ability, &mut board);
+    let used = ClientAction::AbilityUsed {
+        turn: msg.number,
+        player: turn,
+        player_name: msg.info[turn].name.clone(),
+        ability: ability.name.clone(),
+        key: ability.key(),
+        hits: special.hits.clone(),
+        heal: special.heal,
+        hp: board.hp[turn],
+        mana: board.mana[turn],
+    };
+    special.outcome(rule_set, msg, TurnAction::Ability(ability.name.clone()), used, board)
+}
+
+// the player on turn consumes an item of it's inventory instead of attacking
+fn use_item<R: Rng>(
+    rule_set: &BattleRules,
+    msg: &BattleTurnMsg,
+    rng: &mut R,
+    attacker: &PlayerInfo,
+    index: usize,
+    mut board: Board,
+) -> TurnOutcome {
+    let turn = msg.turn;
+    let item = &msg.items[index];
+    items::consume(&mut board.inventory[turn], &item.name);
+    let special = special(rule_set, msg, rng, attacker.power, &item.as_ability(), &mut board);
+    let used = ClientAction::ItemUsed {
+        turn: msg.number,
+        player: turn,
+        player_name: msg.info[turn].name.clone(),
+        item: item.name.clone(),
+        key: item.key(),
+        hits: special.hits.clone(),
+        heal: special.heal,
+        hp: board.hp[turn],
+    };
+    special.outcome(rule_set, msg, TurnAction::Item(item.name.clone()), used, board)
+}
+
+// what an ability or an item did, the players hit with the damage and
+// their HP, the HP healed and the effects applied
+struct Special {
+    defenders: Vec<usize>,
+    hits: Vec<(String, u64, u64)>,
+    heal: u64,
+    effects: Vec<ClientAction>,
+}
+
+// abilities and items never miss, they hit one target or every enemy and
+// heal the player on turn
+fn special<R: Rng>(
+    rule_set: &BattleRules,
+    msg: &BattleTurnMsg,
+    rng: &mut R,
+    power: u64,
+    spec: &Ability,
+    board: &mut Board,
+) -> Special {
+    let (info, turn) = (&msg.info, msg.turn);
+    let defenders = if !spec.offensive() {
+        Vec::new()
+    } else if spec.area {
+        targets(info, &board.hp, turn)
+    } else {
+        vec![select_target(rule_set, msg, rng)]
+    };
+    let mut effects = Vec::new();
+    let mut hits = Vec::new();
+    for &defender in &defenders {
+        let dmg = board.strike(info, defender, spec.damage(power), &mut effects);
+        if let Some(effect) = spec.effect.as_ref().filter(|e| e.kind.harmful()) {
+            board.put_effect(info, turn, defender, dmg, effect, &mut effects);
+        }
+        hits.push((info[defender].name.clone(), dmg, board.hp[defender]));
+    }
+    // healing never goes above the player max HP
+    let before = board.hp[turn];
+    let max_hp = info[turn].max_hp().max(before);
+    board.hp[turn] = before.saturating_add(spec.heal).min(max_hp);
+    if let Some(effect) = spec.effect.as_ref().filter(|e| !e.kind.harmful()) {
+        board.put_effect(info, turn, turn, 0, effect, &mut effects);
+    }
+    Special {
+        defenders,
+        hits,
+        heal: board.hp[turn] - before,
+        effects,
+    }
+}
+
+impl Special {
+    fn outcome(
+        self,
+        rule_set: &BattleRules,
+        msg: &BattleTurnMsg,
+        action: TurnAction,
+        used: ClientAction,
+        board: Board,
+    ) -> TurnOutcome {
+        let (info, turn) = (&msg.info, msg.turn);
+        let (defender, next_turn) = match self.defenders.first() {
+            Some(&d) => (d, rule_set.next_turn(&board.hp, turn, d)),
+            None => (turn, pass_turn(rule_set, info, &board.hp, turn)),
+        };
+        let winner = last_standing(rule_set, info, &board.hp);
+        TurnOutcome {
+            attacker: turn,
+            defender,
+            dice: 0,
+            attack: None,
+            damage: self.hits.iter().map(|h| h.1).sum(),
+            action: Some(action),
+            acted: true,
+            ticks: Vec::new(),
+            effects: self.effects,
+            ability: Some(used),
+            result: board.result(next_turn, winner),
+        }
+    }
+}
+
+// winner among the players alive, when any of them has no enemy left
+fn last_standing(rule_set: &BattleRules, info: &[PlayerInfo], hp: &[u64]) -> Option<(usize, u64)> {
+    (0..hp.len())
+        .filter(|&i| hp[i] > 0)
+        .filter_map(|i| rule_set.winner(&enemy_hp(info, hp, i), i))
+        .next()
+        .map(|w| (w, hp[w]))
+}
+
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn battle_turn<R: Rng, S: BattleEventSink>(
+    rule_set: &BattleRules,
+    msg: &BattleTurnMsg,
+    rng: &mut R,
+    sink: &mut S,
+) -> Result<TurnResultMsg, Error> {
+    let (info, number) = (&msg.info, msg.number);
+    let outcome = resolve_turn(rule_set, msg, rng)?;
+    let player_name = info[outcome.attacker].name.clone();
+    for action in &outcome.ticks {
+        sink.action(action.clone());
+    }
+    if !outcome.acted {
+        sink.replay(ReplayEvent::Dice(outcome.dice));
+        return Ok(outcome.result);
+    }
+    if let Some(used) = outcome.ability {
+        sink.action(used);
+        sink.replay(ReplayEvent::Dice(outcome.dice));
+        for action in outcome.effects {
+            sink.action(action);
+        }
+        return Ok(outcome.result);
+    }
+    match outcome.action {
+        Some(TurnAction::Defend) => {
+            sink.action(ClientAction::Defend {
+                turn: number,
+                player: outcome.attacker,
+                player_name,
+            });
+            sink.replay(ReplayEvent::Dice(outcome.dice));
+            return Ok(outcome.result);
+        }
+        Some(TurnAction::Heal) => {
+            sink.action(ClientAction::Heal {
+                turn: number,
+                player: outcome.attacker,
+                player_name,
+                hp: outcome.result.hp[outcome.attacker],
+            });
+            sink.replay(ReplayEvent::Dice(outcome.dice));
+            return Ok(outcome.result);
+        }
+        _ => (),
+    }
+    sink.action(ClientAction::PlayerAction {
+        turn: number,
+        attacker: outcome.attacker,
+        attacker_name: info[outcome.attacker].name.clone(),
+        defender: outcome.defender,
+        defender_name: info[outcome.defender].name.clone(),
+    });
+    sink.replay(ReplayEvent::Dice(outcome.dice));
+    if let Some(ref attack) = outcome.attack {
+        sink.action(ClientAction::AttackResult {
+            turn: number,
+            attack: attack.attack_type(),
+            key: attack.key(),
+            damage: outcome.damage,
+            defender: outcome.defender,
+            hp: outcome.result.hp[outcome.defender],
+        });
+    }
+    for action in outcome.effects {
+        sink.action(action);
+    }
+    Ok(outcome.result)
+}
+
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn battle_warm_up(
+pub fn battle_warm_up<S: BattleEventSink>(
     players: usize,
     current_players: usize,
-    io: Addr<EnginePipeIo>,
+    sink: &mut S,
 ) -> Result<bool, Error> {
     if current_players < players {
-        // Send message asynchronously to Game IO Actor mailbox (stdout)
-        let _ = io.do_send(ClientAction::AskPlayerInfo(current_players + 1));
+        sink.action(ClientAction::AskPlayerInfo(current_players + 1));
         return Ok(false);
     } else {
         return Ok(true);
@@ -137,16 +760,81 @@ pub fn battle_warm_up(
 }
 
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn battle_announce(player_names: Vec<String>, io: Addr<EnginePipeIo>) -> Result<(), Error> {
-    // Send message asynchronously to Game IO Actor mailbox (stdout)
-    let _ = io.do_send(ClientAction::Start);
-    let _ = io.do_send(ClientAction::AnnouncePlayers(player_names));
+pub fn invalid_player_info<S: BattleEventSink>(reason: String, sink: &mut S) -> Result<(), Error> {
+    sink.action(ClientAction::Message(reason));
+    Ok(())
+}
+
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn battle_announce<S: BattleEventSink>(
+    player_names: Vec<String>,
+    seed: u64,
+    sink: &mut S,
+) -> Result<(), Error> {
+    sink.action(ClientAction::Start);
+    // the seed is announced so the battle can be replayed with the same dice
+    sink.action(ClientAction::Seed(seed));
+    sink.action(ClientAction::AnnouncePlayers(player_names));
+    Ok(())
+}
+
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn battle_over<S: BattleEventSink>(
+    winner: usize,
+    info: &[PlayerInfo],
+    hp: &[u64],
+    sink: &mut S,
+) -> Result<(), Error> {
+    let team = info[winner].team.clone();
+    // every member of the winning team still alive
+    let survivors = match team {
+        Some(_) => (0..hp.len())
+            .filter(|&i| hp[i] > 0 && (i == winner || allies(info, winner, i)))
+            .map(|i| (info[i].name.clone(), hp[i]))
+            .collect(),
+        None => Vec::new(),
+    };
+    sink.action(ClientAction::Winner {
+        player_name: info[winner].name.clone(),
+        hp: hp[winner],
+        team,
+        survivors,
+    });
+    Ok(())
+}
+
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn ask_target<S: BattleEventSink>(msg: &BattleTurnMsg, sink: &mut S) -> Result<(), Error> {
+    sink.action(ClientAction::AskTarget {
+        attacker_name: msg.info[msg.turn].name.clone(),
+        targets: targets(&msg.info, &msg.hp, msg.turn)
+            .into_iter()
+            .map(|i| msg.info[i].name.clone())
+            .collect(),
+    });
+    Ok(())
+}
+
+/// Question for the player on turn in interactive battles
+pub fn ask_action(msg: &BattleTurnMsg, timeout: u64) -> ClientAction {
+    ClientAction::AskAction {
+        attacker_name: msg.info[msg.turn].name.clone(),
+        targets: targets(&msg.info, &msg.hp, msg.turn)
+            .into_iter()
+            .map(|i| msg.info[i].name.clone())
+            .collect(),
+        timeout,
+    }
+}
+
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn action_timeout<S: BattleEventSink>(msg: &BattleTurnMsg, sink: &mut S) -> Result<(), Error> {
+    sink.action(ClientAction::ActionTimeout(msg.info[msg.turn].name.clone()));
     Ok(())
 }
 
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn battle_over(player_name: String, hp: u64, io: Addr<EnginePipeIo>) -> Result<(), Error> {
-    // Send message asynchronously to Game IO Actor mailbox (stdout)
-    let _ = io.do_send(ClientAction::Winner(player_name,hp));
+pub fn ask_rematch<S: BattleEventSink>(sink: &mut S) -> Result<(), Error> {
+    sink.action(ClientAction::AskRematch);
     Ok(())
 }
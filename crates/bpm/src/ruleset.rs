@@ -0,0 +1,161 @@
+//! Rule sets deciding how a battle is played, selected by name on
+//! configuration so variants ship without forking bpm
+use core::{Attack, BattleTurnMsg, PlayerInfo, TurnResultMsg};
+use failure::Error;
+use rand::RngCore;
+use rules;
+use script::{ScriptRules, SCRIPT_RULES};
+use wasm::{WasmLimits, WasmRules, WASM_RULES};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Rule set used when none is configured
+pub const DEFAULT_RULES: &str = "classic";
+
+/// Everything deciding the outcome of a turn
+pub trait BattleRules: Send + Sync {
+    /// Name selecting the rule set on configuration
+    fn name(&self) -> &str;
+
+    /// Player attacked by the player on turn
+    fn select_target(&self, info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize;
+
+    /// Attack of the configured table fired by a dice roll
+    fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack>;
+
+    /// Damage dealt by an attack
+    fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64;
+
+    /// Player attacking on the next turn
+    fn next_turn(&self, hp: &[u64], turn: usize, defender: usize) -> usize;
+
+    /// The winner, if the battle is over after a turn
+    fn winner(&self, hp: &[u64], turn: usize) -> Option<usize>;
+
+    /// Plays the whole turn from the turn message and it's dice roll, for
+    /// rule sets resolving turns outside of Rust like wasm modules, when
+    /// None the turn is played through the other functions
+    fn play_turn(&self, _msg: &BattleTurnMsg, _dice: u8) -> Option<Result<TurnResultMsg, Error>> {
+        None
+    }
+
+    /// Hands the dice of the game to rule sets rolling their own, like
+    /// scripts, before the turn is played
+    fn start_turn(&self, _rng: &mut RngCore) {}
+
+    /// Error the rule set ran into playing the turn, it fails the turn
+    fn finish_turn(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// next player alive after the given one, for more than 2 players
+fn next_alive(hp: &[u64], from: usize) -> usize {
+    let mut p = from;
+    loop {
+        p = if p == hp.len() - 1 { 0 } else { p + 1 };
+        if hp[p] > 0 || p == from {
+            return p;
+        }
+    }
+}
+
+/// The original rules, every player attacks the next one alive with
+/// damage decided by the attack table
+pub struct Classic;
+
+impl BattleRules for Classic {
+    fn name(&self) -> &str {
+        "classic"
+    }
+
+    fn select_target(&self, _info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+        next_alive(hp, turn)
+    }
+
+    fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack> {
+        rules::find_attack(attacks, dice).cloned()
+    }
+
+    fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64 {
+        attack.damage(attacker.power)
+    }
+
+    fn next_turn(&self, hp: &[u64], _turn: usize, defender: usize) -> usize {
+        // the defender strikes back, or the next one alive
+        if hp[defender] > 0 {
+            defender
+        } else {
+            next_alive(hp, defender)
+        }
+    }
+
+    fn winner(&self, hp: &[u64], turn: usize) -> Option<usize> {
+        if rules::is_winner(hp.iter(), turn) {
+            Some(turn)
+        } else {
+            None
+        }
+    }
+}
+
+/// Classic rules where every player finishes off the weakest player alive
+pub struct Focus;
+
+impl BattleRules for Focus {
+    fn name(&self) -> &str {
+        "focus"
+    }
+
+    fn select_target(&self, _info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+        hp.iter()
+            .enumerate()
+            .filter(|&(i, h)| i != turn && *h > 0)
+            .min_by_key(|&(_, h)| *h)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| next_alive(hp, turn))
+    }
+
+    fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack> {
+        Classic.resolve_attack(attacks, dice)
+    }
+
+    fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64 {
+        Classic.damage(attack, attacker)
+    }
+
+    fn next_turn(&self, hp: &[u64], turn: usize, _defender: usize) -> usize {
+        next_alive(hp, turn)
+    }
+
+    fn winner(&self, hp: &[u64], turn: usize) -> Option<usize> {
+        Classic.winner(hp, turn)
+    }
+}
+
+/// Rule set by configuration name
+pub fn by_name(name: &str) -> Result<Arc<BattleRules>, Error> {
+    match name {
+        "classic" => Ok(Arc::new(Classic)),
+        "focus" => Ok(Arc::new(Focus)),
+        n => Err(format_err!("Unknown rule set {}", n)),
+    }
+}
+
+/// Rule set by configuration name, the `script` rule set is played by
+/// the given Rhai script file and the `wasm` one by the given module
+pub fn load(name: &str, file: Option<&Path>, limits: WasmLimits) -> Result<Arc<BattleRules>, Error> {
+    match (name, file) {
+        (SCRIPT_RULES, Some(file)) => Ok(Arc::new(ScriptRules::load(file)?)),
+        (WASM_RULES, Some(file)) => Ok(Arc::new(WasmRules::load(file, limits)?)),
+        (SCRIPT_RULES, None) => Err(format_err!(
+            "Rule set {} needs a Rhai script file on the script key",
+            name
+        )),
+        (WASM_RULES, None) => Err(format_err!(
+            "Rule set {} needs a .wasm module file on the script key",
+            name
+        )),
+        (n, _) => by_name(n),
+    }
+}
@@ -0,0 +1,223 @@
+//! Rule sets written as Rhai scripts, so game masters prototype rules
+//! without touching Rust. Scripts are sandboxed, they only see the
+//! battle state passed to every hook and the `roll()` dice function,
+//! there is no file system or network access from a script and a hook
+//! is stopped after `MAX_OPERATIONS` or `MAX_CALL_LEVELS` nested calls.
+//!
+//! A script defines any of these functions, the ones missing fall back
+//! to the classic rules:
+//!
+//! * `select_target(hp, power, turn, energy, names)` index of the player attacked
+//! * `damage(attack, power, name, energy)` damage of the named attack
+//! * `next_turn(hp, turn, defender)` index of the next attacker
+//! * `winner(hp, turn)` index of the winner or -1 while the battle goes on
+//!
+//! A hook failing, or answering a player that doesn't exist, fails the turn.
+use core::{Attack, PlayerInfo};
+use failure::Error;
+use rand::RngCore;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FuncArgs, RegisterFn, Scope, AST};
+use rules::{self, DiceRng};
+use ruleset::{BattleRules, Classic};
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+
+/// Rule set name selecting a script on configuration
+pub const SCRIPT_RULES: &str = "script";
+
+/// Operations a hook runs before it's stopped
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+/// Nested function calls a hook makes before it's stopped
+pub const MAX_CALL_LEVELS: usize = 32;
+
+// hooks a script may define with the number of arguments they take
+const HOOKS: &[(&str, usize)] = &[("select_target", 5), ("damage", 4), ("next_turn", 3), ("winner", 2)];
+
+// dice and first error of the turn played on the thread, the rules are
+// shared by the simulation threads so they can't keep them
+struct ScriptTurn {
+    rng: DiceRng,
+    error: Option<Error>,
+}
+
+thread_local! {
+    static TURN: RefCell<ScriptTurn> = RefCell::new(ScriptTurn {
+        rng: rules::seeded_rng(0),
+        error: None,
+    });
+}
+
+/// Rules played by a Rhai script
+pub struct ScriptRules {
+    name: String,
+    engine: Engine,
+    ast: AST,
+}
+
+// value of a script call, a missing hook is not an error
+enum Call<T> {
+    Value(T),
+    Missing,
+}
+
+fn int_array(values: &[u64]) -> Array {
+    values.iter().map(|v| Dynamic::from(*v as i64)).collect()
+}
+
+fn names(info: &[PlayerInfo]) -> Array {
+    info.iter().map(|i| Dynamic::from(i.name.clone())).collect()
+}
+
+// keeps the first error of the turn, the turn plays on and fails after
+fn report<T>(result: Result<Option<T>, Error>) -> Option<T> {
+    result.unwrap_or_else(|e| {
+        TURN.with(|t| {
+            t.borrow_mut().error.get_or_insert(e);
+        });
+        None
+    })
+}
+
+impl ScriptRules {
+    /// Rules from a script source compiled once, every hook it defines must
+    /// take the arguments it's called with. Errors running the hooks fail
+    /// the turn they happen on
+    pub fn new(name: &str, source: &str) -> Result<Self, Error> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        // rolls come from the dice of the game, so replays still match
+        engine.register_fn("roll", || -> i64 {
+            TURN.with(|t| i64::from(rules::roll_dice(&mut t.borrow_mut().rng)))
+        });
+        let ast = engine
+            .compile(source)
+            .map_err(|e| format_err!("Rules script {} {}", name, e))?;
+        for &(hook, arguments) in HOOKS {
+            let defined: Vec<usize> = ast
+                .iter_functions()
+                .filter(|&(_, f, _, _)| f == hook)
+                .map(|(_, _, params, _)| params)
+                .collect();
+            if !defined.is_empty() && !defined.contains(&arguments) {
+                return Err(format_err!(
+                    "Rules script {} {} takes {} arguments, not {}",
+                    name,
+                    hook,
+                    arguments,
+                    defined[0]
+                ));
+            }
+        }
+        Ok(ScriptRules {
+            name: String::from(name),
+            engine,
+            ast,
+        })
+    }
+
+    /// Rules from a script file, named after the file
+    pub fn load(file: &Path) -> Result<Self, Error> {
+        let source = fs::read_to_string(file)
+            .map_err(|e| format_err!("Rules script {} {}", file.display(), e))?;
+        let name = file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from(SCRIPT_RULES));
+        ScriptRules::new(&name, &source)
+    }
+
+    fn call<A: FuncArgs>(&self, hook: &str, args: A) -> Result<Call<i64>, Error> {
+        match self.engine.call_fn(&mut Scope::new(), &self.ast, hook, args) {
+            Ok(value) => Ok(Call::Value(value)),
+            Err(e) => match *e {
+                // only the hook itself missing plays the classic rules
+                EvalAltResult::ErrorFunctionNotFound(ref f, _) if f == hook => Ok(Call::Missing),
+                _ => Err(format_err!("Rules script {} {} {}", self.name, hook, e)),
+            },
+        }
+    }
+
+    // Index returned by a script hook, the classic rules decide when it's
+    // missing, and when it fails or is out of the players the turn fails
+    fn index<A, F>(&self, hook: &str, args: A, players: usize, classic: F) -> usize
+    where
+        A: FuncArgs,
+        F: FnOnce() -> usize,
+    {
+        let index = self.call(hook, args).and_then(|call| match call {
+            Call::Value(i) if i >= 0 && (i as usize) < players => Ok(Some(i as usize)),
+            Call::Value(i) => Err(format_err!(
+                "Rules script {} {} answered player {} of {}",
+                self.name,
+                hook,
+                i,
+                players
+            )),
+            Call::Missing => Ok(None),
+        });
+        report(index).unwrap_or_else(classic)
+    }
+}
+
+impl BattleRules for ScriptRules {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn select_target(&self, info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+        let power: Vec<u64> = info.iter().map(|i| i.power).collect();
+        let energy: Vec<u64> = info.iter().map(|i| i.energy).collect();
+        let args = (int_array(hp), int_array(&power), turn as i64, int_array(&energy), names(info));
+        self.index("select_target", args, hp.len(), || Classic.select_target(info, hp, turn))
+    }
+
+    fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack> {
+        Classic.resolve_attack(attacks, dice)
+    }
+
+    fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64 {
+        let args = (
+            attack.name.clone(),
+            attacker.power as i64,
+            attacker.name.clone(),
+            attacker.energy as i64,
+        );
+        let damage = self.call("damage", args).map(|call| match call {
+            Call::Value(d) => Some(d.max(0) as u64),
+            Call::Missing => None,
+        });
+        report(damage).unwrap_or_else(|| Classic.damage(attack, attacker))
+    }
+
+    fn next_turn(&self, hp: &[u64], turn: usize, defender: usize) -> usize {
+        let args = (int_array(hp), turn as i64, defender as i64);
+        self.index("next_turn", args, hp.len(), || Classic.next_turn(hp, turn, defender))
+    }
+
+    fn winner(&self, hp: &[u64], turn: usize) -> Option<usize> {
+        let winner = self.call("winner", (int_array(hp), turn as i64)).and_then(|call| match call {
+            Call::Value(w) if w >= 0 && (w as usize) < hp.len() => Ok(Some(Some(w as usize))),
+            Call::Value(w) if w < 0 => Ok(Some(None)),
+            Call::Value(w) => Err(format_err!(
+                "Rules script {} winner answered player {} of {}",
+                self.name,
+                w,
+                hp.len()
+            )),
+            Call::Missing => Ok(None),
+        });
+        report(winner).unwrap_or_else(|| Classic.winner(hp, turn))
+    }
+
+    fn start_turn(&self, rng: &mut RngCore) {
+        let seed = rng.next_u64();
+        TURN.with(|t| t.borrow_mut().rng = rules::seeded_rng(seed));
+    }
+
+    fn finish_turn(&self) -> Result<(), Error> {
+        TURN.with(|t| t.borrow_mut().error.take()).map_or(Ok(()), Err)
+    }
+}
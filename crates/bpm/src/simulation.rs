@@ -0,0 +1,281 @@
+//! Headless battles played straight on the rules, without any IO actor,
+//! to balance the attack table and damage rules
+use core::{
+    Ability, Attack, AttackType, BattleTurnMsg, Item, PlayerInfo, Targeting, TurnAction, TurnOrder,
+};
+use failure::Error;
+use initiative;
+use rand::Rng;
+use rules;
+use ruleset::BattleRules;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+
+/// Battles still running after this many turns are counted as draws
+pub const MAX_TURNS: u64 = 10_000;
+
+/// Winner HP buckets, each one is 10% of the winner energy
+pub const HP_BUCKETS: usize = 10;
+
+/// How the simulated battles are played, the settings a game takes
+/// from configuration
+#[derive(Debug, Clone, Default)]
+pub struct BattleSettings {
+    pub attacks: Vec<Attack>,
+    pub targeting: Option<Targeting>,
+    pub turn_order: Option<TurnOrder>,
+    pub abilities: Vec<Ability>,
+    pub items: Vec<Item>,
+}
+
+/// Statistics of a simulation
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationReport {
+    pub rules: String,
+    pub seed: u64,
+    pub runs: u64,
+    pub players: Vec<String>,
+    /// Battles won by each player
+    pub wins: Vec<u64>,
+    /// Battles without a winner after MAX_TURNS
+    pub draws: u64,
+    /// Turns played on every battle
+    pub turns: u64,
+    /// Remaining HP of the winners, as 10% buckets of their energy
+    pub hp_distribution: Vec<u64>,
+    /// How many times each attack type fired
+    pub attacks: HashMap<AttackType, u64>,
+    /// Dice rolls outside of the attack table
+    pub no_attack: u64,
+    /// How many times each ability was used instead of attacking
+    pub abilities: HashMap<String, u64>,
+    /// How many times each item was consumed instead of attacking
+    pub items: HashMap<String, u64>,
+}
+
+impl SimulationReport {
+    pub fn new(rules: &str, seed: u64, info: &[PlayerInfo]) -> Self {
+        SimulationReport {
+            rules: String::from(rules),
+            seed,
+            runs: 0,
+            players: info.iter().map(|i| i.name.clone()).collect(),
+            wins: vec![0; info.len()],
+            draws: 0,
+            turns: 0,
+            hp_distribution: vec![0; HP_BUCKETS],
+            attacks: HashMap::new(),
+            no_attack: 0,
+            abilities: HashMap::new(),
+            items: HashMap::new(),
+        }
+    }
+
+    /// Adds up the statistics of another part of the same simulation
+    pub fn merge(&mut self, other: SimulationReport) {
+        self.runs += other.runs;
+        self.draws += other.draws;
+        self.turns += other.turns;
+        self.no_attack += other.no_attack;
+        for (w, o) in self.wins.iter_mut().zip(other.wins) {
+            *w += o;
+        }
+        for (h, o) in self.hp_distribution.iter_mut().zip(other.hp_distribution) {
+            *h += o;
+        }
+        for (attack, count) in other.attacks {
+            *self.attacks.entry(attack).or_insert(0) += count;
+        }
+        for (ability, count) in other.abilities {
+            *self.abilities.entry(ability).or_insert(0) += count;
+        }
+        for (item, count) in other.items {
+            *self.items.entry(item).or_insert(0) += count;
+        }
+    }
+
+    pub fn win_rate(&self, player: usize) -> f64 {
+        ratio(self.wins[player], self.runs)
+    }
+
+    pub fn average_turns(&self) -> f64 {
+        ratio(self.turns, self.runs)
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Simulation of {} battles with {} rules and seed {}",
+            self.runs, self.rules, self.seed
+        )?;
+        writeln!(f, "Win rates:")?;
+        for (i, name) in self.players.iter().enumerate() {
+            writeln!(
+                f,
+                "  {}: {} ({:.2}%)",
+                name,
+                self.wins[i],
+                100.0 * self.win_rate(i)
+            )?;
+        }
+        writeln!(f, "  draws: {}", self.draws)?;
+        writeln!(f, "Average turns: {:.2}", self.average_turns())?;
+        writeln!(f, "Winner HP distribution:")?;
+        let wins: u64 = self.wins.iter().sum();
+        for (i, count) in self.hp_distribution.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>3}-{:>3}%: {} ({:.2}%)",
+                i * 100 / HP_BUCKETS,
+                (i + 1) * 100 / HP_BUCKETS,
+                count,
+                100.0 * ratio(*count, wins)
+            )?;
+        }
+        writeln!(f, "Attacks:")?;
+        let mut attacks: Vec<_> = self.attacks.iter().collect();
+        attacks.sort();
+        for (attack, count) in attacks {
+            writeln!(
+                f,
+                "  {}: {} ({:.2}%)",
+                attack.0,
+                count,
+                100.0 * ratio(*count, self.turns)
+            )?;
+        }
+        writeln!(
+            f,
+            "  no attack: {} ({:.2}%)",
+            self.no_attack,
+            100.0 * ratio(self.no_attack, self.turns)
+        )?;
+        for &(title, uses) in &[("Abilities", &self.abilities), ("Items", &self.items)] {
+            if uses.is_empty() {
+                continue;
+            }
+            writeln!(f, "{}:", title)?;
+            let mut uses: Vec<_> = uses.iter().collect();
+            uses.sort();
+            for (name, count) in uses {
+                writeln!(
+                    f,
+                    "  {}: {} ({:.2}%)",
+                    name,
+                    count,
+                    100.0 * ratio(*count, self.turns)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Plays one battle until there is a winner, adding it to the report
+#[cfg_attr(feature = "flame_it", flame)]
+pub fn play_battle<R: Rng>(
+    rule_set: &BattleRules,
+    settings: &BattleSettings,
+    info: &[PlayerInfo],
+    rng: &mut R,
+    report: &mut SimulationReport,
+) -> Result<(), Error> {
+    let hp = info.iter().map(|i| i.energy).collect();
+    let mut msg = BattleTurnMsg {
+        number: 0,
+        targeting: settings.targeting,
+        turn_order: settings.turn_order,
+        abilities: settings.abilities.clone(),
+        items: settings.items.clone(),
+        ..BattleTurnMsg::new(settings.attacks.clone(), info.to_vec(), hp)
+    };
+    initiative::open(&mut msg, rng);
+    report.runs += 1;
+    for _ in 0..MAX_TURNS {
+        msg.number += 1;
+        let outcome = rules::resolve_turn(rule_set, &msg, rng)?;
+        report.turns += 1;
+        match (&outcome.attack, &outcome.action) {
+            (&Some(ref a), _) => *report.attacks.entry(a.attack_type()).or_insert(0) += 1,
+            (&None, &Some(TurnAction::Ability(ref name))) if outcome.ability.is_some() => {
+                *report.abilities.entry(name.clone()).or_insert(0) += 1
+            }
+            (&None, &Some(TurnAction::Item(ref name))) if outcome.ability.is_some() => {
+                *report.items.entry(name.clone()).or_insert(0) += 1
+            }
+            _ => report.no_attack += 1,
+        }
+        if let Some((w, w_hp)) = outcome.result.winner {
+            report.wins[w] += 1;
+            // in u128 as the HP may be as large as an u64 goes
+            let buckets = HP_BUCKETS as u128;
+            let energy = u128::from(info[w].energy.max(1));
+            let bucket = (u128::from(w_hp) * buckets / energy).min(buckets - 1);
+            report.hp_distribution[bucket as usize] += 1;
+            return Ok(());
+        }
+        msg.hp = outcome.result.hp;
+        msg.defending = outcome.result.defending;
+        msg.schedule = outcome.result.schedule;
+        msg.effects = outcome.result.effects;
+        msg.mana = outcome.result.mana;
+        msg.cooldowns = outcome.result.cooldowns;
+        msg.inventory = outcome.result.inventory;
+        msg.turn = outcome.result.next_turn;
+    }
+    report.draws += 1;
+    Ok(())
+}
+
+/// Plays `runs` battles between the players split over `threads` threads.
+/// Every battle has it's own dice seeded from the simulation seed and the
+/// battle number, so the report is the same whatever the number of threads
+pub fn simulate(
+    rule_set: Arc<BattleRules>,
+    settings: &BattleSettings,
+    info: &[PlayerInfo],
+    runs: u64,
+    seed: u64,
+    threads: usize,
+) -> Result<SimulationReport, Error> {
+    if info.len() < 2 || info.iter().any(|i| i.energy == 0) {
+        return Err(format_err!("Simulation needs at least 2 players with energy"));
+    }
+    let threads = threads.max(1) as u64;
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let rule_set = rule_set.clone();
+            let settings = settings.clone();
+            let info = info.to_vec();
+            thread::spawn(move || -> Result<SimulationReport, Error> {
+                let mut report = SimulationReport::new(rule_set.name(), seed, &info);
+                let mut run = t;
+                while run < runs {
+                    let mut rng = rules::seeded_rng(seed.wrapping_add(run));
+                    play_battle(&*rule_set, &settings, &info, &mut rng, &mut report)?;
+                    run += threads;
+                }
+                Ok(report)
+            })
+        }).collect();
+    let mut report = SimulationReport::new(rule_set.name(), seed, info);
+    for handle in handles {
+        let part = handle
+            .join()
+            .map_err(|_| format_err!("Simulation thread panicked"))??;
+        report.merge(part);
+    }
+    Ok(report)
+}
@@ -0,0 +1,45 @@
+//! Where the rules emit the battle events, so rules don't depend on any
+//! actor and can feed the IO connector, tests or simulations alike
+use actix::Addr;
+use core::{ClientAction, ReplayEvent};
+use engine_io::EnginePipeIo;
+
+/// Receives every event emitted by the rules
+pub trait BattleEventSink {
+    /// Message for the players
+    fn action(&mut self, action: ClientAction);
+
+    /// Event only needed to replay the battle, like the dice rolled
+    fn replay(&mut self, _event: ReplayEvent) {}
+}
+
+/// Adapter sending the events to the Game IO Actor mailbox
+pub struct IoSink(pub Addr<EnginePipeIo>);
+
+impl BattleEventSink for IoSink {
+    fn action(&mut self, action: ClientAction) {
+        // Send message asynchronously to Game IO Actor mailbox (stdout)
+        let _ = self.0.do_send(action);
+    }
+
+    fn replay(&mut self, event: ReplayEvent) {
+        let _ = self.0.do_send(event);
+    }
+}
+
+/// In memory collector of every event, in emission order
+#[derive(Debug, Default)]
+pub struct EventLog {
+    pub actions: Vec<ClientAction>,
+    pub replay: Vec<ReplayEvent>,
+}
+
+impl BattleEventSink for EventLog {
+    fn action(&mut self, action: ClientAction) {
+        self.actions.push(action);
+    }
+
+    fn replay(&mut self, event: ReplayEvent) {
+        self.replay.push(event);
+    }
+}
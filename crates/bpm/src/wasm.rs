@@ -0,0 +1,354 @@
+//! Rule sets compiled to WebAssembly, played by an embedded interpreter
+//! without any JIT. Modules only see the turn handed to them, every
+//! import besides the fuel meter is refused so there is no IO at all.
+//!
+//! Module ABI, version 2:
+//!
+//! * `memory` the exported linear memory
+//! * `alloc(len: i32) -> i32` reserves `len` bytes returning it's offset
+//! * `resolve_turn(ptr: i32, len: i32, dice: i32) -> i64` plays the json
+//!   `WasmTurn` found at `ptr` with the dice rolled by the host,
+//!   returning the json `WasmResult` as `offset << 32 | length`
+//! * `abi_version() -> i32` must be 2
+//!
+//! The json turn and result are frozen for every ABI version, they don't
+//! follow the battle messages of the host. Version 2 added the attack
+//! `base` and the team and attributes of the players. Modules with a
+//! start function are refused.
+use core::{Attack, BattleTurnMsg, PlayerInfo, TurnResultMsg};
+use failure::Error;
+use parity_wasm::elements::{self, MemoryType};
+use pwasm_utils::{self, rules as gas};
+use ruleset::{BattleRules, Classic};
+use serde_json;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use wasmi::{
+    Externals, FuncInstance, FuncRef, HostError, ImportsBuilder, MemoryRef, Module,
+    ModuleImportResolver, ModuleInstance, ModuleRef, RuntimeArgs, RuntimeValue, Signature, Trap,
+    TrapKind, ValueType,
+};
+
+/// Rule set name selecting a wasm module on configuration
+pub const WASM_RULES: &str = "wasm";
+
+/// ABI version implemented by the host
+pub const ABI_VERSION: i32 = 2;
+
+/// Attack handed to a module
+#[derive(Debug, Serialize)]
+pub struct WasmAttack {
+    pub name: String,
+    pub from: u8,
+    pub to: u8,
+    pub mul: u64,
+    pub div: u64,
+    pub bonus: u64,
+    pub base: u64,
+}
+
+/// Player handed to a module, with it's team and attributes
+#[derive(Debug, Serialize)]
+pub struct WasmPlayer {
+    pub name: String,
+    pub energy: u64,
+    pub power: u64,
+    pub team: Option<String>,
+    pub defense: u64,
+    pub evasion: u8,
+    pub crit: u8,
+    pub regen: u64,
+    pub max_hp: u64,
+}
+
+/// Turn handed to a module
+#[derive(Debug, Serialize)]
+pub struct WasmTurn {
+    pub attacks: Vec<WasmAttack>,
+    pub info: Vec<WasmPlayer>,
+    pub hp: Vec<u64>,
+    pub turn: usize,
+    pub number: u64,
+}
+
+/// Result answered by a module
+#[derive(Debug, Deserialize)]
+pub struct WasmResult {
+    pub hp: Vec<u64>,
+    pub next_turn: usize,
+    pub winner: Option<(usize, u64)>,
+}
+
+impl<'a> From<&'a BattleTurnMsg> for WasmTurn {
+    fn from(msg: &BattleTurnMsg) -> Self {
+        WasmTurn {
+            attacks: msg
+                .attacks
+                .iter()
+                .map(|a| WasmAttack {
+                    name: a.name.clone(),
+                    from: a.from,
+                    to: a.to,
+                    mul: a.mul,
+                    div: a.div,
+                    bonus: a.bonus,
+                    base: a.base,
+                })
+                .collect(),
+            info: msg
+                .info
+                .iter()
+                .map(|p| WasmPlayer {
+                    name: p.name.clone(),
+                    energy: p.energy,
+                    power: p.power,
+                    team: p.team.clone(),
+                    defense: p.attributes.defense,
+                    evasion: p.attributes.evasion,
+                    crit: p.attributes.crit,
+                    regen: p.attributes.regen,
+                    max_hp: p.attributes.max_hp.unwrap_or(p.energy),
+                })
+                .collect(),
+            hp: msg.hp.clone(),
+            turn: msg.turn,
+            number: msg.number,
+        }
+    }
+}
+
+impl WasmResult {
+    /// Result of the turn, a module only plays the HP so the rest of the
+    /// battle state carries on as the turn had it
+    pub fn into_turn_result(self, msg: &BattleTurnMsg) -> TurnResultMsg {
+        TurnResultMsg {
+            hp: self.hp,
+            next_turn: self.next_turn,
+            winner: self.winner,
+            defending: msg.defending.clone(),
+            schedule: msg.schedule.clone(),
+            effects: msg.effects.clone(),
+            mana: msg.mana.clone(),
+            cooldowns: msg.cooldowns.clone(),
+            inventory: msg.inventory.clone(),
+        }
+    }
+}
+
+/// Resources a module may use playing a turn
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Fuel burned by the instructions of a turn
+    pub fuel: u64,
+    /// Linear memory, in 64KiB pages
+    pub memory_pages: u32,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        WasmLimits {
+            fuel: 1_000_000,
+            memory_pages: 16,
+        }
+    }
+}
+
+/// Module ran out of fuel
+#[derive(Debug)]
+struct OutOfFuel;
+
+impl fmt::Display for OutOfFuel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "out of fuel")
+    }
+}
+
+impl HostError for OutOfFuel {}
+
+const GAS_INDEX: usize = 0;
+
+// only the fuel meter injected on the module can be imported
+struct GasResolver;
+
+impl ModuleImportResolver for GasResolver {
+    fn resolve_func(&self, field: &str, _signature: &Signature) -> Result<FuncRef, ::wasmi::Error> {
+        match field {
+            "gas" => Ok(FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32][..], None),
+                GAS_INDEX,
+            )),
+            f => Err(::wasmi::Error::Instantiation(format!("Import {} is not allowed", f))),
+        }
+    }
+}
+
+// fuel left for the running turn
+struct Fuel(u64);
+
+impl Externals for Fuel {
+    fn invoke_index(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            GAS_INDEX => {
+                let cost: u32 = args.nth_checked(0)?;
+                if u64::from(cost) > self.0 {
+                    self.0 = 0;
+                    return Err(Trap::new(TrapKind::Host(Box::new(OutOfFuel))));
+                }
+                self.0 -= u64::from(cost);
+                Ok(None)
+            }
+            _ => Err(Trap::new(TrapKind::Unreachable)),
+        }
+    }
+}
+
+/// Rules played by a wasm module
+pub struct WasmRules {
+    name: String,
+    module: Module,
+    limits: WasmLimits,
+}
+
+impl WasmRules {
+    /// Rules from the module bytes, instrumented with the fuel meter and
+    /// capped to the memory limit
+    pub fn new(name: &str, bytes: &[u8], limits: WasmLimits) -> Result<Self, Error> {
+        let mut module: elements::Module = elements::deserialize_buffer(bytes)
+            .map_err(|e| format_err!("Rules module {} {}", name, e))?;
+        // a start function would run outside of any turn and it's fuel
+        if module.start_section().is_some() {
+            return Err(format_err!("Rules module {} has a start function", name));
+        }
+        if let Some(memory) = module.memory_section_mut() {
+            for entry in memory.entries_mut() {
+                let initial = entry.limits().initial();
+                if initial > limits.memory_pages {
+                    return Err(format_err!(
+                        "Rules module {} needs {} memory pages, limit is {}",
+                        name,
+                        initial,
+                        limits.memory_pages
+                    ));
+                }
+                *entry = MemoryType::new(initial, Some(limits.memory_pages));
+            }
+        }
+        let module = pwasm_utils::inject_gas_counter(module, &gas::Set::default())
+            .map_err(|_| format_err!("Rules module {} can't be metered", name))?;
+        let module = Module::from_parity_wasm_module(module)
+            .map_err(|e| format_err!("Rules module {} {}", name, e))?;
+        let rules = WasmRules {
+            name: String::from(name),
+            module,
+            limits,
+        };
+        // checks the exports and the abi version once
+        let (instance, _) = rules.instantiate()?;
+        for export in &["abi_version", "alloc", "resolve_turn"] {
+            if instance.export_by_name(export).is_none() {
+                return Err(format_err!("Rules module {} doesn't export {}", name, export));
+            }
+        }
+        let version = rules.invoke(&instance, "abi_version", &[], &mut Fuel(limits.fuel))?;
+        if version != RuntimeValue::I32(ABI_VERSION) {
+            return Err(format_err!("Rules module {} abi {:?} is not supported", name, version));
+        }
+        Ok(rules)
+    }
+
+    /// Rules from a module file, named after the file
+    pub fn load(file: &Path, limits: WasmLimits) -> Result<Self, Error> {
+        let bytes = fs::read(file).map_err(|e| format_err!("Rules module {} {}", file.display(), e))?;
+        let name = file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from(WASM_RULES));
+        WasmRules::new(&name, &bytes, limits)
+    }
+
+    // every turn runs on a fresh instance, so nothing is kept between
+    // turns and the same turn always plays the same
+    fn instantiate(&self) -> Result<(ModuleRef, MemoryRef), Error> {
+        let imports = ImportsBuilder::new().with_resolver("env", &GasResolver);
+        let instance = ModuleInstance::new(&self.module, &imports)
+            .map_err(|e| format_err!("Rules module {} {}", self.name, e))?
+            .assert_no_start();
+        let memory = instance
+            .export_by_name("memory")
+            .and_then(|e| e.as_memory().cloned())
+            .ok_or_else(|| format_err!("Rules module {} doesn't export memory", self.name))?;
+        Ok((instance, memory))
+    }
+
+    fn invoke(
+        &self,
+        instance: &ModuleRef,
+        export: &str,
+        args: &[RuntimeValue],
+        fuel: &mut Fuel,
+    ) -> Result<RuntimeValue, Error> {
+        instance
+            .invoke_export(export, args, fuel)
+            .map_err(|e| match fuel.0 {
+                0 => format_err!("Rules module {} {} out of fuel", self.name, export),
+                _ => format_err!("Rules module {} {} {}", self.name, export, e),
+            })?
+            .ok_or_else(|| format_err!("Rules module {} {} returned nothing", self.name, export))
+    }
+
+    /// Plays a turn on the module
+    pub fn resolve_turn(&self, msg: &BattleTurnMsg, dice: u8) -> Result<TurnResultMsg, Error> {
+        let (instance, memory) = self.instantiate()?;
+        let mut fuel = Fuel(self.limits.fuel);
+        let input = serde_json::to_vec(&WasmTurn::from(msg))?;
+        let len = RuntimeValue::I32(input.len() as i32);
+        let ptr = match self.invoke(&instance, "alloc", &[len], &mut fuel)? {
+            RuntimeValue::I32(p) => p,
+            v => return Err(format_err!("Rules module {} alloc returned {:?}", self.name, v)),
+        };
+        memory
+            .set(ptr as u32, &input)
+            .map_err(|e| format_err!("Rules module {} {}", self.name, e))?;
+        let args = [RuntimeValue::I32(ptr), len, RuntimeValue::I32(i32::from(dice))];
+        let packed = match self.invoke(&instance, "resolve_turn", &args, &mut fuel)? {
+            RuntimeValue::I64(p) => p as u64,
+            v => return Err(format_err!("Rules module {} resolve_turn returned {:?}", self.name, v)),
+        };
+        let output = memory
+            .get((packed >> 32) as u32, (packed & 0xffff_ffff) as usize)
+            .map_err(|e| format_err!("Rules module {} {}", self.name, e))?;
+        let result: WasmResult = serde_json::from_slice(&output)?;
+        Ok(result.into_turn_result(msg))
+    }
+}
+
+impl BattleRules for WasmRules {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn select_target(&self, info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+        Classic.select_target(info, hp, turn)
+    }
+
+    fn resolve_attack(&self, attacks: &[Attack], dice: u8) -> Option<Attack> {
+        Classic.resolve_attack(attacks, dice)
+    }
+
+    fn damage(&self, attack: &Attack, attacker: &PlayerInfo) -> u64 {
+        Classic.damage(attack, attacker)
+    }
+
+    fn next_turn(&self, hp: &[u64], turn: usize, defender: usize) -> usize {
+        Classic.next_turn(hp, turn, defender)
+    }
+
+    fn winner(&self, hp: &[u64], turn: usize) -> Option<usize> {
+        Classic.winner(hp, turn)
+    }
+
+    fn play_turn(&self, msg: &BattleTurnMsg, dice: u8) -> Option<Result<TurnResultMsg, Error>> {
+        Some(self.resolve_turn(msg, dice))
+    }
+}
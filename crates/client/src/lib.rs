@@ -18,21 +18,33 @@ extern crate actix;
 extern crate tokio;
 extern crate app_dir as dirs;
 extern crate fconfig;
+extern crate i18n;
 extern crate core;
 extern crate atty;
 extern crate crossbeam_channel as channel;
+extern crate futures;
+extern crate num_cpus;
+extern crate serde_json;
 
+use actix::actors::signal;
 use actix::prelude::*;
 use dirs::Directories;
-use engine::{GameActor, GameContext};
-use engine_io::EnginePipeIo;
-use bpm::Bpm;
+use engine::{GameActor, GameContext, GameFactory, Series, ShutdownMsg};
+use engine_io::{replay, ClientActionCodec, CloseMsg, EnginePipeIo, OutputFormat};
+use bpm::ruleset::BattleRules;
+use bpm::{simulation, Bpm};
 use failure::Error;
+use futures::Future;
+use std::net::SocketAddr;
 use std::path::Path;
-use core::{StartBattleMsg, PlayerInfo};
+use std::sync::Arc;
+use std::time::Duration;
+use core::{CharacterClass, StartBattleMsg, PlayerInfo, PlayerInput};
 use atty::{is, Stream};
 
 const CONFIG_FILENAME: &str = "Settings.toml";
+const CLASSES_FILENAME: &str = "classes.toml";
+const ITEMS_FILENAME: &str = "items.toml";
 
 #[derive(Debug, Fail)]
 /// Set of errors that can occurr during client processing
@@ -43,7 +55,8 @@ pub enum ClientError {
     Msg(String),
 }
 
-pub fn run<I, T>(args: I) -> Result<(), Error>
+/// Runs the app returning the process exit code
+pub fn run<I, T>(args: I) -> Result<i32, Error>
 where
     I: IntoIterator<Item = T>,
     T: Into<::std::ffi::OsString> + Clone,
@@ -66,6 +79,12 @@ where
     if let Some(bpath) = base {
         d.base = String::from(bpath);
     }
+    d.replays = dirs::replays_root_path(&d.base)
+        .into_string()
+        .map_err(|_| ClientError::OsString)?;
+    d.scripts = dirs::scripts_root_path(&d.base)
+        .into_string()
+        .map_err(|_| ClientError::OsString)?;
     let _ = d.create_dirs();
     #[cfg(feature="flame_init")]
     flame::end("app data setup");    
@@ -75,7 +94,7 @@ where
     flame::start("config handling");  
     let mut app_config = fconfig::AppConfig::default();
     let config_param = matches.value_of("config");
-    let mut config_file_name: String = dirs::get_base_file(d.base, CONFIG_FILENAME)
+    let mut config_file_name: String = dirs::get_base_file(&d.base, CONFIG_FILENAME)
         .into_string()
         .map_err(|_| ClientError::OsString)?;
     if let Some(c) = config_param {
@@ -88,10 +107,101 @@ where
         if file_config.players.is_some() {
             app_config.players = file_config.players;
         }
-        if !file_config.range.is_empty() {
-            app_config.range = file_config.range.iter().cloned().collect();
+        if !file_config.attacks.is_empty() {
+            app_config.attacks = file_config.attacks;
+        }
+        if file_config.seed.is_some() {
+            app_config.seed = file_config.seed;
+        }
+        if file_config.connector.is_some() {
+            app_config.connector = file_config.connector;
+        }
+        if file_config.listen.is_some() {
+            app_config.listen = file_config.listen;
+        }
+        if file_config.output.is_some() {
+            app_config.output = file_config.output;
+        }
+        if file_config.locale.is_some() {
+            app_config.locale = file_config.locale;
+        }
+        if file_config.input_retries.is_some() {
+            app_config.input_retries = file_config.input_retries;
+        }
+        if file_config.rematch.is_some() {
+            app_config.rematch = file_config.rematch;
+        }
+        if file_config.max_games.is_some() {
+            app_config.max_games = file_config.max_games;
+        }
+        if file_config.reuse_players.is_some() {
+            app_config.reuse_players = file_config.reuse_players;
+        }
+        if file_config.rules.is_some() {
+            app_config.rules = file_config.rules;
+        }
+        if file_config.targeting.is_some() {
+            app_config.targeting = file_config.targeting;
+        }
+        if file_config.turn_order.is_some() {
+            app_config.turn_order = file_config.turn_order;
+        }
+        if !file_config.teams.is_empty() {
+            app_config.teams = file_config.teams;
+        }
+        if file_config.interactive.is_some() {
+            app_config.interactive = file_config.interactive;
+        }
+        if file_config.action_timeout.is_some() {
+            app_config.action_timeout = file_config.action_timeout;
+        }
+        if file_config.player_info_timeout.is_some() {
+            app_config.player_info_timeout = file_config.player_info_timeout;
+        }
+        if file_config.target_timeout.is_some() {
+            app_config.target_timeout = file_config.target_timeout;
+        }
+        if file_config.rematch_timeout.is_some() {
+            app_config.rematch_timeout = file_config.rematch_timeout;
+        }
+        if file_config.on_timeout.is_some() {
+            app_config.on_timeout = file_config.on_timeout;
+        }
+        if file_config.script.is_some() {
+            app_config.script = file_config.script;
+        }
+        if file_config.wasm_fuel.is_some() {
+            app_config.wasm_fuel = file_config.wasm_fuel;
+        }
+        if file_config.wasm_memory.is_some() {
+            app_config.wasm_memory = file_config.wasm_memory;
+        }
+        if !file_config.roster.is_empty() {
+            app_config.roster = file_config.roster;
+        }
+        if !file_config.classes.is_empty() {
+            app_config.classes = file_config.classes;
+        }
+        if !file_config.abilities.is_empty() {
+            app_config.abilities = file_config.abilities;
+        }
+        if !file_config.items.is_empty() {
+            app_config.items = file_config.items;
+        }
+        if !file_config.loadouts.is_empty() {
+            app_config.loadouts = file_config.loadouts;
         }
     }
+    // class catalog data file of the app data folder wins over the
+    // configured classes
+    let classes_file = Path::new(&d.base).join(CLASSES_FILENAME);
+    if let Some(classes) = fconfig::load_classes(&classes_file)? {
+        app_config.classes = classes;
+    }
+    let items_file = Path::new(&d.base).join(ITEMS_FILENAME);
+    if let Some(items) = fconfig::load_items(&items_file)? {
+        app_config.items = items;
+    }
     #[cfg(feature="flame_init")]
     flame::end("config handling"); 
 
@@ -105,39 +215,169 @@ where
     #[cfg(feature="flame_init")]
     flame::end("players handling");     
 
+    // Dice seed argument initialization, when missing a random one is
+    // generated for every game and announced so the battle can be
+    // reproduced later
+    let seed = matches.value_of("seed");
+    if let Some(s) = seed {
+        app_config.seed = Some(s.parse()?);
+    }
+
+    // Connector arguments initialization
+    if let Some(c) = matches.value_of("connector") {
+        app_config.connector = Some(String::from(c));
+    }
+    if let Some(l) = matches.value_of("listen") {
+        app_config.listen = Some(String::from(l));
+    }
+    let connector = app_config.connector.clone().unwrap_or(String::from("pipe"));
+
+    // Series arguments initialization
+    if matches.is_present("rematch") {
+        app_config.rematch = Some(true);
+    }
+    if matches.is_present("interactive") {
+        app_config.interactive = Some(true);
+    }
+    if let Some(g) = matches.value_of("games") {
+        app_config.max_games = Some(g.parse()?);
+    }
+
+    // Output format argument initialization
+    if let Some(o) = matches.value_of("output") {
+        app_config.output = Some(String::from(o));
+    }
+    let format: OutputFormat = app_config
+        .output
+        .clone()
+        .unwrap_or(String::from("text"))
+        .parse()?;
+
+    // Message catalog of the user language loaded once at startup,
+    // configured locale wins over the LANG environment variable
+    let locale = app_config
+        .locale
+        .clone()
+        .or_else(i18n::locale_from_env)
+        .unwrap_or(String::from(i18n::DEFAULT_LOCALE));
+    let catalog = Arc::new(i18n::Catalog::load(
+        &locale,
+        Some(&Path::new(&d.base).join("locales")),
+    )?);
+    let codec = ClientActionCodec::new(format, catalog);
+
+    // replay mode just plays a recorded battle back, there is no game to setup
+    if let Some(replay_matches) = matches.subcommand_matches("replay") {
+        let file = replay_matches.value_of("file").unwrap();
+        return replay_battle(file, &d, codec);
+    }
+
+    // Attack table, class, ability and item catalogs are checked once,
+    // before any battle
+    bpm::rules::check_attacks(&app_config.attacks)?;
+    bpm::rules::check_classes(&app_config.classes)?;
+    bpm::rules::check_abilities(&app_config.abilities, &app_config.classes)?;
+    bpm::rules::check_items(&app_config.items, &app_config.abilities, &app_config.loadouts)?;
+
+    if let Some(classes_matches) = matches.subcommand_matches("classes") {
+        if classes_matches.subcommand_matches("list").is_some() {
+            return list_classes(&app_config.classes);
+        }
+    }
+
+    // Rule set by name, shared by every game, scripts and wasm modules
+    // are loaded from the scripts folder
+    let script = app_config
+        .script
+        .as_ref()
+        .map(|s| Path::new(&d.scripts).join(s));
+    let default_limits = bpm::wasm::WasmLimits::default();
+    let limits = bpm::wasm::WasmLimits {
+        fuel: app_config.wasm_fuel.unwrap_or(default_limits.fuel),
+        memory_pages: app_config.wasm_memory.unwrap_or(default_limits.memory_pages),
+    };
+    let rule_set = bpm::ruleset::load(
+        app_config
+            .rules
+            .as_ref()
+            .map(String::as_str)
+            .unwrap_or(bpm::ruleset::DEFAULT_RULES),
+        script.as_ref().map(|s| s.as_path()),
+        limits,
+    )?;
+
+    // simulate mode plays headless battles straight on the rules
+    if let Some(simulate_matches) = matches.subcommand_matches("simulate") {
+        return simulate_battles(simulate_matches, &app_config, rule_set);
+    }
+
     // Create new Reactor for Reative programming
     let sys = System::new("fantasy");
+    // only the network connectors listen on an address
+    let listen = listen_addr(&app_config);
 
-    // Starting actors 
-    // Initialize the Stdin Stdout connector running in a thread-pool
-    // with just one real thread
-    #[cfg(feature="flame_init")]
-    flame::start("connector setup");  
-    let (s, r) = channel::bounded::<PlayerInfo>(1000);
-    let io_addr: Addr<EnginePipeIo> = EnginePipeIo::create(move |ctx| {
-        ctx.set_mailbox_capacity(1000);
-        let in_pipe = is(Stream::Stdin);
-        let out_pipe = is(Stream::Stdout);
-        let sender = s.clone();
-        let writer = None;
-        let writer_pipe = None;
-        EnginePipeIo{in_pipe, out_pipe, sender, writer, writer_pipe}
-    });
-    #[cfg(feature="flame_init")]
-    flame::end("connector setup");     
+    // Game factory, every game gets it's own IO connector running in a
+    // thread-pool with just one real thread, it's own BPM module running
+    // in a distinct thread-pool with just one real thread and it's own
+    // player input channel
+    let stdio = connector == "pipe";
+    let replays = d.replays.clone();
+    let game_codec = codec.clone();
+    let factory: GameFactory = Box::new(move |id| {
+        let mut config = app_config.clone();
+        let seed = config.seed.unwrap_or_else(bpm::rules::random_seed);
+        config.seed = Some(seed);
+        #[cfg(feature="flame_init")]
+        flame::start("connector setup");
+        let (sender, r) = channel::bounded::<PlayerInput>(1000);
+        // every battle is recorded to the replays folder, a battle is still
+        // played when the replay file can not be created
+        let replay_file = Path::new(&replays).join(replay::file_name(seed, id));
+        let recorder = replay::Recorder::create(replay_file).ok();
+        let codec = game_codec.clone();
+        let io_addr: Addr<EnginePipeIo> = EnginePipeIo::create(move |ctx| {
+            ctx.set_mailbox_capacity(1000);
+            let in_pipe = is(Stream::Stdin);
+            let out_pipe = is(Stream::Stdout);
+            let writer = None;
+            let writer_pipe = None;
+            let sessions = Vec::new();
+            let entering = Vec::new();
+            let action = None;
+            let target = None;
+            let flushed = None;
+            EnginePipeIo{stdio, codec, in_pipe, out_pipe, sender, writer, writer_pipe, recorder, sessions, entering, action, target, flushed}
+        });
+        #[cfg(feature="flame_init")]
+        flame::end("connector setup");
 
-    let io_addr_bpm = io_addr.clone();
-    // Initialize BPM module running in a distinct thread-pool with
-    // just one real thread
-    #[cfg(feature="flame_init")]
-    flame::start("bpm setup");    
-    let bpm_addr: Addr<Bpm> = SyncArbiter::start(1, move || {               
-        Bpm(io_addr_bpm.clone(), r.clone())
+        let io_addr_bpm = io_addr.clone();
+        #[cfg(feature="flame_init")]
+        flame::start("bpm setup");
+        let rules = rule_set.clone();
+        // interactive battles wait for the action of the player on turn
+        let action_timeout = if config.interactive.unwrap_or(false) {
+            Some(Duration::from_secs(config.action_timeout.unwrap_or(30)))
+        } else {
+            None
+        };
+        let target_timeout = config.target_timeout.map(Duration::from_secs);
+        let bpm_addr: Addr<Bpm> = SyncArbiter::start(1, move || {
+            Bpm::new(io_addr_bpm.clone(), r.clone(), seed, rules.clone(), action_timeout, target_timeout)
+        });
+        #[cfg(feature="flame_init")]
+        flame::end("bpm setup");
+
+        let context = GameContext {
+            config,
+            io: io_addr,
+            bpm: bpm_addr,
+            series: Series::default(),
+        };
+        Ok(context)
     });
-    #[cfg(feature="flame_init")]
-    flame::end("bpm setup");    
 
-    // Game Actor Factory, it's async sharing one real thread sharing
+    // Game Actor hosting every game, it's async sharing one real thread sharing
     // as much as green / light thread's as there is memory avaliable on
     // hardware enabling it to sustain under stress millions of requests
     #[cfg(feature="flame_init")]
@@ -148,22 +388,193 @@ where
         // any new requests is immediately dropped in order to not compromise
         // operation of the server and backfiring attacks
         ctx.set_mailbox_capacity(1000);
-        GameActor(GameContext {
-            config: app_config,
-            io: io_addr,
-            bpm: bpm_addr,
-        })
+        GameActor::new(factory)
     });
     #[cfg(feature="flame_init")]
     flame::end("game setup");    
 
-    // Send message asynchronously to Game Actor to Start New Game
-    let _ = game_addr.do_send(StartBattleMsg{});
+    // servers shut down on interrupt or terminate, after their running
+    // games are over
+    if connector != "pipe" {
+        let signals = System::current().registry().get::<signal::ProcessSignals>();
+        signals.do_send(signal::Subscribe(game_addr.clone().recipient()));
+    }
+
+    match connector.as_str() {
+        "pipe" => {
+            // Send message asynchronously to Game Actor to Start the stdin
+            // stdout game, the process stops when it's over
+            let _ = game_addr.do_send(StartBattleMsg{});
+            let _ = game_addr.do_send(ShutdownMsg);
+        }
+        "tcp" => {
+            // every socket connecting to the server enters one player on the
+            // first game with a free seat, the server runs until stopped
+            let _ = engine_io::tcp::listen(
+                &listen?,
+                game_addr.recipient(),
+                codec,
+            )?;
+        }
+        "ws" => {
+            // every websocket connecting to the server enters one player on
+            // the first game with a free seat, the server runs until stopped
+            engine_io::ws::listen(&listen?, game_addr.recipient())?;
+        }
+        c => return Err(ClientError::Msg(format!("Unknown connector {}", c)).into()),
+    }
     // Start main reactor and blocks main thread until a terminate message
     // comes to the System Actor message box
-    let _ = sys.run();
+    Ok(sys.run())
+}
 
-    Ok(())
+/// Address the network connectors listen on
+fn listen_addr(app_config: &fconfig::AppConfig) -> Result<SocketAddr, Error> {
+    let listen = app_config
+        .listen
+        .clone()
+        .unwrap_or(String::from("127.0.0.1:7878"));
+    Ok(listen.parse()?)
+}
+
+/// Plays back a recorded battle on the IO connector, the file is looked up
+/// on the replays folder when it's not found on the given path
+fn replay_battle(file: &str, d: &Directories, codec: ClientActionCodec) -> Result<i32, Error> {
+    let path = if Path::new(file).is_file() {
+        Path::new(file).to_path_buf()
+    } else {
+        Path::new(&d.replays).join(file)
+    };
+    let events = replay::load(path)?;
+
+    let sys = System::new("fantasy");
+    // input is never read on playback
+    let (s, _r) = channel::bounded::<PlayerInput>(1);
+    let io_addr: Addr<EnginePipeIo> = EnginePipeIo::create(move |ctx| {
+        ctx.set_mailbox_capacity(1000);
+        let stdio = true;
+        let in_pipe = is(Stream::Stdin);
+        let out_pipe = is(Stream::Stdout);
+        let sender = s.clone();
+        let writer = None;
+        let writer_pipe = None;
+        let recorder = None;
+        let sessions = Vec::new();
+        let entering = Vec::new();
+        let action = None;
+        let target = None;
+        let flushed = None;
+        EnginePipeIo{stdio, codec, in_pipe, out_pipe, sender, writer, writer_pipe, recorder, sessions, entering, action, target, flushed}
+    });
+    // the process stops once the writer flushed the whole playback
+    let io = io_addr.clone();
+    Arbiter::spawn(
+        replay::playback(events, io_addr)
+            .then(move |_| io.send(CloseMsg))
+            .then(|_| Ok(System::current().stop())),
+    );
+    Ok(sys.run())
+}
+
+/// Plays headless battles between the players of the command line, or of
+/// the roster configuration, printing the statistics report
+fn simulate_battles(
+    args: &clap::ArgMatches,
+    app_config: &fconfig::AppConfig,
+    rule_set: Arc<BattleRules>,
+) -> Result<i32, Error> {
+    let runs: u64 = args.value_of("runs").unwrap_or("1000").parse()?;
+    let threads = match args.value_of("threads") {
+        Some(t) => t.parse()?,
+        None => num_cpus::get(),
+    };
+    let roster: Vec<String> = match args.values_of("player") {
+        Some(players) => players.map(String::from).collect(),
+        None => app_config.roster.clone(),
+    };
+    // a player without items takes the loadout of it's seat
+    let mut info = roster
+        .iter()
+        .enumerate()
+        .map(|(seat, p)| {
+            let info = core::expand_class(&app_config.classes, p.parse()?)?;
+            let mut info = core::check_player_abilities(&app_config.abilities, info)?;
+            if info.items.is_empty() {
+                if let Some(loadout) = app_config.loadouts.get(seat) {
+                    info.items = loadout.clone();
+                }
+            }
+            core::equip(&app_config.items, info)
+        })
+        .collect::<Result<Vec<PlayerInfo>, Error>>()?;
+    // a player without a team takes the one of it's seat
+    for (p, team) in info.iter_mut().zip(&app_config.teams) {
+        if p.team.is_none() {
+            p.team = Some(team.clone());
+        }
+    }
+    let seed = app_config.seed.unwrap_or_else(bpm::rules::random_seed);
+    let settings = simulation::BattleSettings {
+        attacks: app_config.attacks.clone(),
+        targeting: app_config.targeting,
+        turn_order: app_config.turn_order,
+        abilities: app_config.abilities.clone(),
+        items: app_config.items.clone(),
+    };
+    let report = simulation::simulate(
+        rule_set,
+        &settings,
+        &info,
+        runs,
+        seed,
+        threads,
+    )?;
+    match args.value_of("format").unwrap_or("text") {
+        "json" => println!("{}", serde_json::to_string(&report)?),
+        _ => print!("{}", report),
+    }
+    Ok(0)
+}
+
+/// Prints the class catalog with the stats of every class at level 1 and
+/// what it gains on every level after it
+fn list_classes(classes: &[CharacterClass]) -> Result<i32, Error> {
+    for c in classes {
+        println!("{}: {}", c.name, c.description);
+        let a = &c.attributes;
+        let mut stats = format!("  energy {} power {}", c.energy, c.power);
+        if let Some(speed) = a.speed {
+            stats += &format!(" speed={}", speed);
+        }
+        for (key, value) in &[
+            ("defense", a.defense),
+            ("evasion", u64::from(a.evasion)),
+            ("crit", u64::from(a.crit)),
+            ("regen", a.regen),
+            ("mana", a.mana),
+        ] {
+            if *value > 0 {
+                stats += &format!(" {}={}", key, value);
+            }
+        }
+        if let Some(max_hp) = a.max_hp {
+            stats += &format!(" max_hp={}", max_hp);
+        }
+        if let Some(targeting) = c.targeting {
+            let targeting = serde_json::to_value(targeting)?;
+            stats += &format!(" {}", targeting.as_str().unwrap_or_default());
+        }
+        if !c.abilities.is_empty() {
+            stats += &format!(" +{}", c.abilities.join(" +"));
+        }
+        println!("{}", stats);
+        let g = &c.growth;
+        println!(
+            "  per level: energy +{} power +{} defense +{} speed +{}",
+            g.energy, g.power, g.defense, g.speed
+        );
+    }
+    Ok(0)
 }
 
 // Smoke test
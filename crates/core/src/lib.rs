@@ -17,38 +17,658 @@ use actix::Message;
 use failure::Error;
 use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub enum AttackType {
-    Miss,
-    Standard,
-    Lucky,
-    Critical,
-    Undefined
+/// Name of the attack fired by a dice roll, like `Lucky`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AttackType(pub String);
+
+impl AttackType {
+    /// Message key displaying the attack when the table doesn't define one
+    pub fn default_key(&self) -> String {
+        format!("attack-{}", self.0.to_lowercase())
+    }
 }
 
-// language specific display, should i18n this on the future
+// default pt-BR display, client output is rendered by the i18n catalogs
 impl fmt::Display for AttackType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match self.0.as_ref() {
+            "Miss" => "Errou !",
+            "Standard" => "Normal",
+            "Lucky" => "Sorte!!!",
+            "Critical" => "Crítico!",
+            name => name,
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+fn one() -> u64 {
+    1
+}
+
+// value * mul / div without overflowing, saturating at u64::MAX
+fn scale(value: u64, mul: u64, div: u64) -> u64 {
+    let scaled = u128::from(value) * u128::from(mul) / u128::from(div.max(1));
+    if scaled > u128::from(u64::max_value()) {
+        u64::max_value()
+    } else {
+        scaled as u64
+    }
+}
+
+/// Entry of the attack table, the attack fires when the dice falls on it's
+/// range and deals `power / base * mul / div + bonus` damage
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attack {
+    pub name: String,
+    /// Start of the dice range, dice rolls go from 0 to 99
+    pub from: u8,
+    /// End of the dice range, not included
+    pub to: u8,
+    #[serde(default = "one")]
+    pub mul: u64,
+    #[serde(default = "one")]
+    pub div: u64,
+    #[serde(default)]
+    pub bonus: u64,
+    /// Divides the power before `mul`, the classic attacks work on the
+    /// standard damage of 1/3 of the power
+    #[serde(default = "one")]
+    pub base: u64,
+    /// Message key displaying the attack, `attack-<name>` in lowercase by default
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Status effect the attack applies when it fires
+    #[serde(default)]
+    pub effect: Option<Effect>,
+}
+
+impl Attack {
+    pub fn new(name: &str, from: u8, to: u8, mul: u64, div: u64) -> Self {
+        Attack {
+            name: String::from(name),
+            from,
+            to,
+            mul,
+            div,
+            bonus: 0,
+            base: 1,
+            key: None,
+            effect: None,
+        }
+    }
+
+    pub fn attack_type(&self) -> AttackType {
+        AttackType(self.name.clone())
+    }
+
+    pub fn key(&self) -> String {
+        self.key
+            .clone()
+            .unwrap_or_else(|| self.attack_type().default_key())
+    }
+
+    pub fn damage(&self, power: u64) -> u64 {
+        scale(power / self.base.max(1), self.mul, self.div).saturating_add(self.bonus)
+    }
+}
+
+/// The original attacks, a missed attack deals no damage, a standard one
+/// 1/3 of the power, a lucky one 20% more than standard and a critical
+/// one two times the standard
+pub fn classic_attacks() -> Vec<Attack> {
+    let standard = |name, from, to, mul, div| Attack {
+        base: 3,
+        ..Attack::new(name, from, to, mul, div)
+    };
+    vec![
+        Attack::new("Miss", 0, 15, 0, 1),
+        standard("Standard", 15, 70, 1, 1),
+        standard("Lucky", 70, 96, 6, 5),
+        standard("Critical", 96, 100, 2, 1),
+    ]
+}
+
+/// How an attacker picks it's target
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Targeting {
+    /// The next player alive in index order
+    NextAlive,
+    /// Any player alive, picked by the dice
+    Random,
+    LowestHp,
+    HighestPower,
+    /// The player is asked for the target on every turn
+    Chosen,
+}
+
+impl FromStr for Targeting {
+    type Err = Error;
+
+    fn from_str(data: &str) -> Result<Targeting, Error> {
+        match data.trim().to_lowercase().as_ref() {
+            "next-alive" => Ok(Targeting::NextAlive),
+            "random" => Ok(Targeting::Random),
+            "lowest-hp" => Ok(Targeting::LowestHp),
+            "highest-power" => Ok(Targeting::HighestPower),
+            "chosen" => Ok(Targeting::Chosen),
+            t => Err(format_err!(
+                "Targeting \"{}\" unknown, expected next-alive, random, lowest-hp, highest-power or chosen",
+                t
+            )),
+        }
+    }
+}
+
+/// Speed of the players without one
+pub const DEFAULT_SPEED: u64 = 10;
+
+/// Who acts after a turn
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TurnOrder {
+    /// The rule set decides, the classic rules rotate by index
+    Rotation,
+    /// Every round the players act from the fastest to the slowest
+    Initiative,
+    /// Players fill an action gauge with their speed and act when it's
+    /// full, so faster players act more often
+    Atb,
+}
+
+/// State of the turn order carried from turn to turn
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Schedule {
+    /// Players still to act on the round, in initiative order
+    #[serde(default)]
+    pub queue: Vec<usize>,
+    /// Action gauge of every player in atb order
+    #[serde(default)]
+    pub gauge: Vec<u64>,
+}
+
+/// Kind of a status effect
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum EffectKind {
+    /// Takes `amount` HP at the start of every turn of the player
+    Poison,
+    /// The player loses it's turns
+    Stun,
+    /// Absorbs up to `amount` damage before the HP is hit
+    Shield,
+    /// Adds `amount` to the power of the player
+    Buff,
+}
+
+impl EffectKind {
+    /// Whether the amounts of the same effect add up, otherwise the
+    /// strongest one is kept. Durations are always refreshed
+    pub fn stacks(&self) -> bool {
+        match *self {
+            EffectKind::Poison | EffectKind::Shield => true,
+            EffectKind::Stun | EffectKind::Buff => false,
+        }
+    }
+
+    /// Whether the effect is applied to the defender, otherwise the
+    /// attacker applies it to itself
+    pub fn harmful(&self) -> bool {
+        match *self {
+            EffectKind::Poison | EffectKind::Stun => true,
+            EffectKind::Shield | EffectKind::Buff => false,
+        }
+    }
+
+    /// Message key displaying the effect
+    pub fn key(&self) -> String {
+        let name = match *self {
+            EffectKind::Poison => "poison",
+            EffectKind::Stun => "stun",
+            EffectKind::Shield => "shield",
+            EffectKind::Buff => "buff",
+        };
+        format!("effect-{}", name)
+    }
+}
+
+// default pt-BR display, client output is rendered by the i18n catalogs
+impl fmt::Display for EffectKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match *self {
-            AttackType::Miss => String::from("Errou !"),
-            AttackType::Standard => String::from("Normal"),
-            AttackType::Lucky => String::from("Sorte!!!"),            
-            AttackType::Critical => String::from("Crítico!"),
-            AttackType::Undefined => String::from(""),
+            EffectKind::Poison => "Veneno",
+            EffectKind::Stun => "Atordoamento",
+            EffectKind::Shield => "Escudo",
+            EffectKind::Buff => "Fúria",
         };
         write!(f, "{}", printable)
     }
 }
 
+/// Status effect lasting some turns of the player it's on
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Effect {
+    pub kind: EffectKind,
+    #[serde(default)]
+    pub amount: u64,
+    /// Turns of the player the effect lasts
+    #[serde(default = "one")]
+    pub turns: u64,
+}
+
+/// Special ability of a character used instead of the attack, it spends
+/// mana and waits some turns of the player before it can be used again
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Ability {
+    pub name: String,
+    /// Mana spent on every use
+    #[serde(default)]
+    pub cost: u64,
+    /// Turns of the player before it can be used again
+    #[serde(default)]
+    pub cooldown: u64,
+    /// Deals `power * mul / div + bonus` damage, no damage when both
+    /// mul and bonus are 0. Abilities never miss
+    #[serde(default)]
+    pub mul: u64,
+    #[serde(default = "one")]
+    pub div: u64,
+    #[serde(default)]
+    pub bonus: u64,
+    /// Hits every enemy alive instead of one target
+    #[serde(default)]
+    pub area: bool,
+    /// HP the player heals, up to it's max HP
+    #[serde(default)]
+    pub heal: u64,
+    /// Status effect put on every player hit, or on the player itself
+    /// when it isn't harmful
+    #[serde(default)]
+    pub effect: Option<Effect>,
+    /// Message key displaying the ability, `ability-<name>` by default
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+impl Ability {
+    pub fn key(&self) -> String {
+        self.key
+            .clone()
+            .unwrap_or_else(|| format!("ability-{}", self.name.to_lowercase()))
+    }
+
+    pub fn damage(&self, power: u64) -> u64 {
+        scale(power, self.mul, self.div).saturating_add(self.bonus)
+    }
+
+    /// Whether the ability is used on enemies
+    pub fn offensive(&self) -> bool {
+        self.mul > 0 || self.bonus > 0 || self.effect.as_ref().map_or(false, |e| e.kind.harmful())
+    }
+}
+
+/// The original abilities, a strike twice the standard attack, a blow
+/// hitting every enemy and a heal
+pub fn classic_abilities() -> Vec<Ability> {
+    let ability = |name: &str, cost, cooldown| Ability {
+        name: String::from(name),
+        cost: cost,
+        cooldown: cooldown,
+        mul: 0,
+        div: 1,
+        bonus: 0,
+        area: false,
+        heal: 0,
+        effect: None,
+        key: None,
+    };
+    let mut heavy_strike = ability("heavy-strike", 10, 2);
+    heavy_strike.mul = 2;
+    heavy_strike.div = 3;
+    let mut whirlwind = ability("whirlwind", 25, 3);
+    whirlwind.mul = 1;
+    whirlwind.div = 4;
+    whirlwind.area = true;
+    let mut mend = ability("mend", 15, 3);
+    mend.heal = 25;
+    vec![heavy_strike, whirlwind, mend]
+}
+
+// default pt-BR display of an ability name, client output is rendered by
+// the i18n catalogs
+fn ability_display(name: &str) -> &str {
+    match name {
+        "heavy-strike" => "Golpe Pesado",
+        "whirlwind" => "Redemoinho",
+        "mend" => "Cura",
+        n => n,
+    }
+}
+
+/// Players hit by an ability or an item with the damage and their HP,
+/// like `B -30 HP (70)`, the player healing itself as `A +25 HP (90)`
+pub fn hit_results(hits: &[(String, u64, u64)], heal: Option<(&str, u64, u64)>) -> String {
+    let mut results: Vec<String> = hits
+        .iter()
+        .map(|&(ref name, damage, hp)| format!("{} -{} HP ({})", name, damage, hp))
+        .collect();
+    if let Some((name, healed, hp)) = heal {
+        results.push(format!("{} +{} HP ({})", name, healed, hp));
+    }
+    results.join(", ")
+}
+
+/// Kind of an item
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemKind {
+    /// Consumed on a turn to heal the player, or to put an effect on it
+    Potion,
+    /// Consumed on a turn to hurt the enemies
+    Bomb,
+    /// Adds it's stats to the player for the whole battle
+    Equipment,
+}
+
+/// Item of the inventory of a player
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Item {
+    pub name: String,
+    pub kind: ItemKind,
+    #[serde(default)]
+    pub description: String,
+    /// HP a potion heals, up to the player max HP
+    #[serde(default)]
+    pub heal: u64,
+    /// Damage a bomb deals, whatever the power of the player
+    #[serde(default)]
+    pub damage: u64,
+    /// The bomb hits every enemy alive instead of one target
+    #[serde(default)]
+    pub area: bool,
+    /// Status effect a potion puts on the player, or a bomb on the players hit
+    #[serde(default)]
+    pub effect: Option<Effect>,
+    /// Energy and power equipment adds
+    #[serde(default)]
+    pub energy: u64,
+    #[serde(default)]
+    pub power: u64,
+    /// Attributes equipment adds
+    #[serde(default)]
+    pub attributes: Attributes,
+    /// Message key displaying the item, `item-<name>` by default
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+impl Item {
+    pub fn key(&self) -> String {
+        self.key
+            .clone()
+            .unwrap_or_else(|| format!("item-{}", self.name.to_lowercase()))
+    }
+
+    pub fn consumable(&self) -> bool {
+        self.kind != ItemKind::Equipment
+    }
+
+    /// Ability the item plays like when consumed, without cost or cooldown
+    pub fn as_ability(&self) -> Ability {
+        Ability {
+            name: self.name.clone(),
+            cost: 0,
+            cooldown: 0,
+            mul: 0,
+            div: 1,
+            bonus: self.damage,
+            area: self.area,
+            heal: self.heal,
+            effect: self.effect.clone(),
+            key: Some(self.key()),
+        }
+    }
+
+    /// Adds the stats of the equipment to the player
+    pub fn equip(&self, info: &mut PlayerInfo) {
+        let (own, extra) = (&mut info.attributes, &self.attributes);
+        if let Some(speed) = extra.speed {
+            own.speed = Some(own.speed.unwrap_or(DEFAULT_SPEED).saturating_add(speed));
+        }
+        own.defense = own.defense.saturating_add(extra.defense);
+        own.evasion = own.evasion.saturating_add(extra.evasion).min(100);
+        own.crit = own.crit.saturating_add(extra.crit).min(100);
+        own.regen = own.regen.saturating_add(extra.regen);
+        own.mana = own.mana.saturating_add(extra.mana);
+        if let Some(max_hp) = extra.max_hp {
+            own.max_hp = Some(own.max_hp.unwrap_or(info.energy).saturating_add(max_hp));
+        }
+        info.energy = info.energy.saturating_add(self.energy);
+        info.power = info.power.saturating_add(self.power);
+    }
+}
+
+/// The original items, a healing potion, a bomb hitting every enemy, and
+/// a sword, an armor and boots to equip
+pub fn classic_items() -> Vec<Item> {
+    let item = |name: &str, kind, description: &str| Item {
+        name: String::from(name),
+        kind: kind,
+        description: String::from(description),
+        heal: 0,
+        damage: 0,
+        area: false,
+        effect: None,
+        energy: 0,
+        power: 0,
+        attributes: Attributes::default(),
+        key: None,
+    };
+    let mut potion = item("potion", ItemKind::Potion, "Heals 30 HP");
+    potion.heal = 30;
+    let mut bomb = item("bomb", ItemKind::Bomb, "Deals 20 damage to every enemy");
+    bomb.damage = 20;
+    bomb.area = true;
+    let mut sword = item("sword", ItemKind::Equipment, "Adds 10 power");
+    sword.power = 10;
+    let mut armor = item("armor", ItemKind::Equipment, "Adds 3 defense and 10 energy");
+    armor.energy = 10;
+    armor.attributes.defense = 3;
+    let mut boots = item("boots", ItemKind::Equipment, "Adds 3 speed");
+    boots.attributes.speed = Some(3);
+    vec![potion, bomb, sword, armor, boots]
+}
+
+// default pt-BR display of an item name, client output is rendered by the
+// i18n catalogs
+fn item_display(name: &str) -> &str {
+    match name {
+        "potion" => "Poção",
+        "bomb" => "Bomba",
+        n => n,
+    }
+}
+
+/// What happens when a player doesn't answer before the deadline of
+/// the game state waiting for it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeoutPolicy {
+    /// A stand-in player takes the seat, a rematch is declined
+    Default,
+    /// The seat is dropped and the battle goes on with the players seated
+    Forfeit,
+    /// The battle is aborted with an error
+    Abort,
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        TimeoutPolicy::Default
+    }
+}
+
+/// Action of the player on turn in interactive battles
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum TurnAction {
+    /// Attack the named player, or the target of the targeting when None
+    Attack(Option<String>),
+    /// Halves the damage taken until the next turn of the player
+    Defend,
+    /// Skips the attack to recover some energy
+    Heal,
+    /// Uses the named ability of the player instead of attacking
+    Ability(String),
+    /// Consumes the named item of the inventory instead of attacking
+    Item(String),
+}
+
+impl<'a> From<&'a str> for TurnAction {
+    /// Conversion from the word entered by the player, any other word
+    /// is the name of the target
+    fn from(data: &'a str) -> Self {
+        match data.trim().to_lowercase().as_ref() {
+            "attack" | "atacar" | "a" => TurnAction::Attack(None),
+            "defend" | "defender" | "d" => TurnAction::Defend,
+            "heal" | "curar" | "skip" | "pular" | "h" => TurnAction::Heal,
+            _ => TurnAction::Attack(Some(String::from(data.trim()))),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientAction {
     AskPlayerInfo(usize),
     Start,
+    Seed(u64),
     AnnouncePlayers(Vec<String>),
-    PlayerAction(String, String),
-    AttackResult(AttackType, u64),
-    Winner(String, u64),
+    /// Turn number, attacker and defender indices and names
+    PlayerAction {
+        turn: u64,
+        attacker: usize,
+        attacker_name: String,
+        defender: usize,
+        defender_name: String,
+    },
+    /// Turn number, attack with it's message key, damage, defender index
+    /// and it's remaining HP
+    AttackResult {
+        turn: u64,
+        attack: AttackType,
+        #[serde(default)]
+        key: String,
+        damage: u64,
+        defender: usize,
+        hp: u64,
+    },
+    /// Winner name and HP, in team battles the winning team with the
+    /// surviving members and their HP
+    Winner {
+        player_name: String,
+        hp: u64,
+        #[serde(default)]
+        team: Option<String>,
+        #[serde(default)]
+        survivors: Vec<(String, u64)>,
+    },
+    AskRematch,
+    /// Attacker name and the players it can choose to attack
+    AskTarget {
+        attacker_name: String,
+        targets: Vec<String>,
+    },
+    /// Player on turn, the players it can attack and the seconds it has
+    /// to answer
+    AskAction {
+        attacker_name: String,
+        targets: Vec<String>,
+        timeout: u64,
+    },
+    /// The player didn't answer in time and plays automatically
+    ActionTimeout(String),
+    /// Input of another player while waiting for the action of the one on turn
+    WaitingAction(String),
+    /// Seat number, from 1, of a player not entering it's info in time and
+    /// the stand-in taking the seat
+    StandIn {
+        seat: usize,
+        player_name: String,
+    },
+    /// Seat number given up by an idle player and how many players the
+    /// battle goes on with
+    Forfeit {
+        seat: usize,
+        players: usize,
+    },
+    /// Seat number of an idle player aborting the battle
+    SeatAborted(usize),
+    /// Nobody answered the rematch in time, the game is over
+    RematchTimeout,
+    /// Nobody answered the rematch in time, the game is aborted
+    RematchAborted,
+    Defend {
+        turn: u64,
+        player: usize,
+        player_name: String,
+    },
+    /// Turn number, player healing and it's HP after healing
+    Heal {
+        turn: u64,
+        player: usize,
+        player_name: String,
+        hp: u64,
+    },
+    /// Status effect put on a player with it's amount and turns
+    EffectApplied {
+        player_name: String,
+        effect: EffectKind,
+        amount: u64,
+        turns: u64,
+    },
+    /// Status effect acting at the start of the turn of the player, the HP
+    /// it took and the player HP, a stunned player loses the turn
+    EffectTick {
+        turn: u64,
+        player: usize,
+        player_name: String,
+        effect: EffectKind,
+        amount: u64,
+        hp: u64,
+    },
+    EffectExpired {
+        player_name: String,
+        effect: EffectKind,
+    },
+    /// Turn number, player using an ability with it's message key, the
+    /// players hit with the damage and their HP, the HP the player healed
+    /// with it's own HP and the mana left
+    AbilityUsed {
+        turn: u64,
+        player: usize,
+        player_name: String,
+        ability: String,
+        key: String,
+        hits: Vec<(String, u64, u64)>,
+        heal: u64,
+        hp: u64,
+        mana: u64,
+    },
+    /// Turn number, player consuming an item with it's message key, the
+    /// players hit with the damage and their HP and the HP the player
+    /// healed with it's own HP
+    ItemUsed {
+        turn: u64,
+        player: usize,
+        player_name: String,
+        item: String,
+        key: String,
+        hits: Vec<(String, u64, u64)>,
+        heal: u64,
+        hp: u64,
+    },
     Message(String),
 }
 
@@ -57,30 +677,263 @@ impl Message for ClientAction {
     type Result = Result<(), Error>;
 }
 
-// language specific display, should i18n this on the future
+// default pt-BR display, client output is rendered by the i18n catalogs
 impl fmt::Display for ClientAction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match *self {
             ClientAction::AskPlayerInfo(ref n) => format!("Entre o personagem {}", n),
             ClientAction::Start => String::from("O jogo começou"),
+            ClientAction::Seed(ref s) => format!("Semente da batalha {}", s),
             ClientAction::AnnouncePlayers(ref p) => format!("Batalha entre {:?}", p),
-            ClientAction::PlayerAction(ref p1, ref p2) => format!("{} atacou {}", p1, p2),
-            ClientAction::AttackResult(ref t, ref d) => format!("{} - {} HP", t, d),
-            ClientAction::Winner(ref p, ref hp) => format!(
+            ClientAction::PlayerAction {
+                ref attacker_name,
+                ref defender_name,
+                ..
+            } => format!("{} atacou {}", attacker_name, defender_name),
+            ClientAction::AttackResult {
+                ref attack,
+                ref damage,
+                ..
+            } => format!("{} - {} HP", attack, damage),
+            ClientAction::Winner {
+                team: Some(ref team),
+                ref survivors,
+                ..
+            } => format!(
+                "Jogo acabou, a equipe {} venceu, sobreviventes: {}",
+                team,
+                survivors
+                    .iter()
+                    .map(|&(ref name, hp)| format!("{} ({} HP)", name, hp))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            ClientAction::Winner {
+                ref player_name,
+                ref hp,
+                ..
+            } => format!(
                 "Jogo acabou, o vencedor foi {} com HP restante de {}",
-                p, hp
+                player_name, hp
+            ),
+            ClientAction::AskRematch => String::from("Jogar novamente? (s/n)"),
+            ClientAction::AskTarget {
+                ref attacker_name,
+                ref targets,
+            } => format!("{}, escolha quem atacar: {}", attacker_name, targets.join(", ")),
+            ClientAction::AskAction {
+                ref attacker_name,
+                ref targets,
+                ref timeout,
+            } => format!(
+                "{}, sua vez: atacar, defender, curar ou o nome do alvo {} ({}s)",
+                attacker_name,
+                targets.join(", "),
+                timeout
             ),
+            ClientAction::ActionTimeout(ref p) => {
+                format!("Tempo esgotado, {} joga automaticamente", p)
+            }
+            ClientAction::WaitingAction(ref p) => format!("Aguardando a ação de {}", p),
+            ClientAction::StandIn {
+                ref seat,
+                ref player_name,
+            } => format!("Jogador {} demorou demais, {} assume o lugar", seat, player_name),
+            ClientAction::Forfeit {
+                ref seat,
+                ref players,
+            } => format!(
+                "Jogador {} demorou demais e desiste, a batalha segue com {} jogadores",
+                seat, players
+            ),
+            ClientAction::SeatAborted(ref seat) => {
+                format!("Jogador {} demorou demais, a batalha foi abortada", seat)
+            }
+            ClientAction::RematchTimeout => {
+                String::from("Ninguém respondeu a revanche a tempo, o jogo acabou")
+            }
+            ClientAction::RematchAborted => {
+                String::from("Ninguém respondeu a revanche a tempo, o jogo foi abortado")
+            }
+            ClientAction::Defend {
+                ref player_name, ..
+            } => format!("{} se defende", player_name),
+            ClientAction::Heal {
+                ref player_name,
+                ref hp,
+                ..
+            } => format!("{} se curou, HP {}", player_name, hp),
+            ClientAction::EffectApplied {
+                ref player_name,
+                ref effect,
+                ref amount,
+                ref turns,
+            } => format!("{} recebe {} ({}) por {} turnos", player_name, effect, amount, turns),
+            ClientAction::EffectTick {
+                ref player_name,
+                effect: EffectKind::Stun,
+                ..
+            } => format!("{} está atordoado e perde a vez", player_name),
+            ClientAction::EffectTick {
+                ref player_name,
+                ref effect,
+                ref amount,
+                ref hp,
+                ..
+            } => format!("{} sofre {} - {} HP, HP {}", player_name, effect, amount, hp),
+            ClientAction::EffectExpired {
+                ref player_name,
+                ref effect,
+            } => format!("{} de {} acabou", effect, player_name),
+            ClientAction::AbilityUsed {
+                ref player_name,
+                ref ability,
+                ref hits,
+                ref heal,
+                ref hp,
+                ..
+            } => {
+                let healed = if *heal > 0 {
+                    Some((player_name.as_str(), *heal, *hp))
+                } else {
+                    None
+                };
+                format!(
+                    "{} usou {}: {}",
+                    player_name,
+                    ability_display(ability),
+                    hit_results(hits, healed)
+                )
+            }
+            ClientAction::ItemUsed {
+                ref player_name,
+                ref item,
+                ref hits,
+                ref heal,
+                ref hp,
+                ..
+            } => {
+                let healed = if *heal > 0 {
+                    Some((player_name.as_str(), *heal, *hp))
+                } else {
+                    None
+                };
+                format!(
+                    "{} usou {}: {}",
+                    player_name,
+                    item_display(item),
+                    hit_results(hits, healed)
+                )
+            }
             ClientAction::Message(ref m) => m.clone(),
         };
         write!(f, "{}", printable)
     }
 }
 
-#[derive(Debug, Clone)]
+/// Optional attributes of a character, entered as `key=value` after the
+/// name, energy and power of the player
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Attributes {
+    /// Speed deciding the turn order, DEFAULT_SPEED when None
+    pub speed: Option<u64>,
+    /// Damage points taken away from every hit
+    pub defense: u64,
+    /// Dice points the Miss range of the attack table grows when attacked
+    pub evasion: u8,
+    /// Dice points the Critical range of the attack table grows when attacking
+    pub crit: u8,
+    /// HP recovered at the start of every turn of the player
+    pub regen: u64,
+    /// HP the player can heal up to, the energy when None
+    pub max_hp: Option<u64>,
+    /// Mana pool the abilities spend, full at the start of the battle
+    pub mana: u64,
+}
+
+/// Attributes entered on a player line, the ones left None come from the
+/// class the player names
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Overrides {
+    pub speed: Option<u64>,
+    pub defense: Option<u64>,
+    pub evasion: Option<u8>,
+    pub crit: Option<u8>,
+    pub regen: Option<u64>,
+    pub max_hp: Option<u64>,
+    pub mana: Option<u64>,
+}
+
+impl Overrides {
+    /// Sets an attribute by it's key
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|e| format_err!("Player {} \"{}\" {}", key, value, e))
+        };
+        let dice = || match number()? {
+            n if n <= 100 => Ok(n as u8),
+            n => Err(format_err!("Player {} {} is over 100", key, n)),
+        };
+        match key {
+            "speed" => self.speed = Some(number()?),
+            "defense" => self.defense = Some(number()?),
+            "evasion" => self.evasion = Some(dice()?),
+            "crit" => self.crit = Some(dice()?),
+            "regen" => self.regen = Some(number()?),
+            "max_hp" => self.max_hp = Some(number()?),
+            "mana" => self.mana = Some(number()?),
+            k => return Err(format_err!("Player attribute \"{}\" unknown", k)),
+        }
+        Ok(())
+    }
+
+    /// The attributes with the entered ones over them
+    pub fn apply(&self, attributes: &Attributes) -> Attributes {
+        Attributes {
+            speed: self.speed.or(attributes.speed),
+            defense: self.defense.unwrap_or(attributes.defense),
+            evasion: self.evasion.unwrap_or(attributes.evasion),
+            crit: self.crit.unwrap_or(attributes.crit),
+            regen: self.regen.unwrap_or(attributes.regen),
+            max_hp: self.max_hp.or(attributes.max_hp),
+            mana: self.mana.unwrap_or(attributes.mana),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PlayerInfo {
     pub name: String,
     pub energy: u64,
     pub power: u64,
+    /// Targeting of the player, the configured one when None
+    #[serde(default)]
+    pub targeting: Option<Targeting>,
+    /// Team of the player, players of the same team never attack each
+    /// other, without a team it's everybody for himself
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default)]
+    pub attributes: Attributes,
+    /// Attributes entered on the line, they win over the ones of the class
+    #[serde(default)]
+    pub overrides: Overrides,
+    /// Character class the stats are expanded from, like `warrior`
+    #[serde(default)]
+    pub class: Option<String>,
+    /// Level of the class, 1 when None
+    #[serde(default)]
+    pub level: Option<u64>,
+    /// Names of the abilities of the player
+    #[serde(default)]
+    pub abilities: Vec<String>,
+    /// Names of the items the player carries, equipment included
+    #[serde(default)]
+    pub items: Vec<String>,
 }
 
 impl FromStr for PlayerInfo {
@@ -89,55 +942,396 @@ impl FromStr for PlayerInfo {
     /// Conversion from str with error handling
     fn from_str(data: &str) -> Result<PlayerInfo, Error> {
         let info_vec: Vec<&str> = data.split_whitespace().collect();
-        if info_vec.len() != 3 {
+        // a class name instead of the energy expands the player from the
+        // class catalog, "name class [level=n] [extras]"
+        let class_line = info_vec.len() >= 2 && info_vec[1].parse::<u64>().is_err()
+            && info_vec[1].chars().all(|c| c.is_alphabetic() || c == '-' || c == '_');
+        if class_line {
+            let mut info = PlayerInfo::new(info_vec[0], 0, 0);
+            info.class = Some(info_vec[1].to_lowercase());
+            info.parse_extras(&info_vec[2..])?;
+            return Ok(info);
+        }
+        if info_vec.len() < 3 {
             // language specific display, should i18n this on the future
-            return Err(format_err!("Player info insuficient params"));
+            return Err(format_err!(
+                "Player info insuficient params, expected \"name energy power [targeting] [@team] [key=value...]\" or \"name class [level]\" got \"{}\"",
+                data.trim()
+            ));
         }
-        let name = String::from(info_vec[0]);
-        let energy = info_vec[1].parse::<u64>()?;
-        let power = info_vec[2].parse::<u64>()?;
-        Ok(PlayerInfo {
-            name: name,
+        let energy = info_vec[1]
+            .parse::<u64>()
+            .map_err(|e| format_err!("Player energy \"{}\" {}", info_vec[1], e))?;
+        let power = info_vec[2]
+            .parse::<u64>()
+            .map_err(|e| format_err!("Player power \"{}\" {}", info_vec[2], e))?;
+        let mut info = PlayerInfo::new(info_vec[0], energy, power);
+        info.parse_extras(&info_vec[3..])?;
+        if info.level.is_some() {
+            return Err(format_err!("Player level needs a class"));
+        }
+        info.attributes = info.overrides.apply(&info.attributes);
+        Ok(info)
+    }
+}
+
+impl PlayerInfo {
+    pub fn new(name: &str, energy: u64, power: u64) -> PlayerInfo {
+        PlayerInfo {
+            name: String::from(name),
             energy: energy,
             power: power,
-        })
+            targeting: None,
+            team: None,
+            attributes: Attributes::default(),
+            overrides: Overrides::default(),
+            class: None,
+            level: None,
+            abilities: Vec::new(),
+            items: Vec::new(),
+        }
+    }
+
+    /// Player taking the seat of the one who didn't enter it's info
+    pub fn stand_in(seat: usize) -> PlayerInfo {
+        PlayerInfo::new(&format!("Bot{}", seat + 1), 100, 50)
+    }
+
+    // targeting, team, abilities, items and key=value attributes or
+    // level follow in any order, the team starts with @, every ability
+    // with + and every item with *
+    fn parse_extras(&mut self, extras: &[&str]) -> Result<(), Error> {
+        for extra in extras {
+            if extra.starts_with('@') && extra.len() > 1 && self.team.is_none() {
+                self.team = Some(String::from(&extra[1..]));
+            } else if extra.starts_with('+') && extra.len() > 1 {
+                self.abilities.push(extra[1..].to_lowercase());
+            } else if extra.starts_with('*') && extra.len() > 1 {
+                self.items.push(extra[1..].to_lowercase());
+            } else if extra.starts_with("level=") {
+                let level = extra[6..]
+                    .parse::<u64>()
+                    .map_err(|e| format_err!("Player level \"{}\" {}", &extra[6..], e))?;
+                self.level = Some(level);
+            } else if let Some(eq) = extra.find('=') {
+                self.overrides.set(&extra[..eq], &extra[eq + 1..])?;
+            } else if self.targeting.is_none() {
+                self.targeting = Some(extra.parse::<Targeting>()?);
+            } else {
+                return Err(format_err!("Player info \"{}\" is repeated", extra));
+            }
+        }
+        Ok(())
+    }
+
+    /// Speed of the player, DEFAULT_SPEED when it has none
+    pub fn speed(&self) -> u64 {
+        self.attributes.speed.unwrap_or(DEFAULT_SPEED)
+    }
+
+    /// HP the player can heal up to, never below it's energy
+    pub fn max_hp(&self) -> u64 {
+        self.attributes.max_hp.unwrap_or(self.energy).max(self.energy)
+    }
+}
+
+/// Stats a class gains on every level after the first
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Growth {
+    pub energy: u64,
+    pub power: u64,
+    pub defense: u64,
+    pub speed: u64,
+}
+
+/// Highest level of a class, higher levels are played at it
+pub const MAX_LEVEL: u64 = 100;
+
+/// Character class a player line names instead of it's stats, like
+/// `Alice warrior level=3`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CharacterClass {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Energy and power at level 1
+    pub energy: u64,
+    pub power: u64,
+    #[serde(default)]
+    pub attributes: Attributes,
+    #[serde(default)]
+    pub growth: Growth,
+    /// Special rule of the class, how it picks it's targets
+    #[serde(default)]
+    pub targeting: Option<Targeting>,
+    /// Abilities of the class, for the players not naming their own
+    #[serde(default)]
+    pub abilities: Vec<String>,
+}
+
+impl CharacterClass {
+    /// Player of the class at the level of the info, attributes and
+    /// targeting the player entered win over the ones of the class
+    pub fn expand(&self, info: &PlayerInfo) -> PlayerInfo {
+        let level = info.level.unwrap_or(1).max(1).min(MAX_LEVEL);
+        let grow = |base: u64, growth: u64| base.saturating_add(growth.saturating_mul(level - 1));
+        let mut class = self.attributes.clone();
+        class.defense = grow(class.defense, self.growth.defense);
+        if self.growth.speed > 0 {
+            class.speed = Some(grow(class.speed.unwrap_or(DEFAULT_SPEED), self.growth.speed));
+        }
+        PlayerInfo {
+            name: info.name.clone(),
+            energy: grow(self.energy, self.growth.energy),
+            power: grow(self.power, self.growth.power),
+            targeting: info.targeting.or(self.targeting),
+            team: info.team.clone(),
+            attributes: info.overrides.apply(&class),
+            overrides: info.overrides.clone(),
+            class: Some(self.name.clone()),
+            level: Some(level),
+            abilities: if info.abilities.is_empty() {
+                self.abilities.clone()
+            } else {
+                info.abilities.clone()
+            },
+            items: info.items.clone(),
+        }
+    }
+}
+
+/// The original classes, a sturdy warrior, a mage hitting hard and a
+/// fast rogue
+pub fn classic_classes() -> Vec<CharacterClass> {
+    let class = |name: &str, description: &str, energy, power| CharacterClass {
+        name: String::from(name),
+        description: String::from(description),
+        energy: energy,
+        power: power,
+        attributes: Attributes::default(),
+        growth: Growth::default(),
+        targeting: None,
+        abilities: Vec::new(),
+    };
+    let mut warrior = class("warrior", "Sturdy fighter taking the hits", 140, 45);
+    warrior.attributes.defense = 4;
+    warrior.attributes.mana = 30;
+    warrior.abilities = vec![String::from("heavy-strike")];
+    warrior.growth = Growth { energy: 14, power: 4, defense: 1, speed: 0 };
+    let mut mage = class("mage", "Frail caster hitting the weakest", 80, 75);
+    mage.attributes.crit = 5;
+    mage.attributes.mana = 60;
+    mage.abilities = vec![String::from("whirlwind"), String::from("mend")];
+    mage.growth = Growth { energy: 6, power: 8, defense: 0, speed: 0 };
+    mage.targeting = Some(Targeting::LowestHp);
+    let mut rogue = class("rogue", "Fast and evasive striker", 100, 55);
+    rogue.attributes.speed = Some(14);
+    rogue.attributes.evasion = 8;
+    rogue.attributes.mana = 40;
+    rogue.abilities = vec![String::from("heavy-strike"), String::from("mend")];
+    rogue.growth = Growth { energy: 9, power: 5, defense: 0, speed: 1 };
+    vec![warrior, mage, rogue]
+}
+
+/// Expands a player line naming a class into the full player, lines with
+/// their own stats are kept as they are
+pub fn expand_class(classes: &[CharacterClass], info: PlayerInfo) -> Result<PlayerInfo, Error> {
+    let name = match info.class {
+        // expanded players already have their stats
+        Some(ref name) if info.energy == 0 => name.clone(),
+        _ => return Ok(info),
+    };
+    match classes.iter().find(|c| c.name.eq_ignore_ascii_case(&name)) {
+        Some(class) => Ok(class.expand(&info)),
+        None => Err(format_err!(
+            "Class \"{}\" unknown, expected one of {}",
+            name,
+            classes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// Checks every ability of a player is on the ability catalog
+pub fn check_player_abilities(abilities: &[Ability], info: PlayerInfo) -> Result<PlayerInfo, Error> {
+    match info.abilities.iter().find(|&a| !abilities.iter().any(|b| b.name.eq_ignore_ascii_case(a))) {
+        Some(unknown) => Err(format_err!(
+            "Ability \"{}\" unknown, expected one of {}",
+            unknown,
+            abilities.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+        None => Ok(info),
     }
 }
 
-impl<'a> From<&'a str> for PlayerInfo {
-    /// Conversion from String to PlayerInfo will
-    /// crash if input is not on format "name energy power"
+/// Equips a player with the equipment it carries, every item must be on
+/// the item catalog
+pub fn equip(items: &[Item], mut info: PlayerInfo) -> Result<PlayerInfo, Error> {
+    for name in info.items.clone() {
+        match items.iter().find(|i| i.name.eq_ignore_ascii_case(&name)) {
+            Some(item) if !item.consumable() => item.equip(&mut info),
+            Some(_) => (),
+            None => {
+                return Err(format_err!(
+                    "Item \"{}\" unknown, expected one of {}",
+                    name,
+                    items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>().join(", ")
+                ))
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// A line entered by a player, malformed lines are kept with the
+/// explanation of the error so the player can be asked again
+#[derive(Debug, Clone)]
+pub enum PlayerInput {
+    Info(PlayerInfo),
+    Answer(bool),
+    /// A single word, like the name of the player chosen as target
+    Choice(String),
+    Invalid(String),
+    /// Nothing entered before the deadline
+    Timeout,
+}
+
+/// PlayerInput messaging enabled, input goes through the IO hub
+impl Message for PlayerInput {
+    type Result = ();
+}
+
+impl<'a> From<&'a str> for PlayerInput {
+    /// Conversion from String to PlayerInput, never fails as
+    /// malformed lines become PlayerInput::Invalid
     fn from(data: &'a str) -> Self {
-        let info_vec: Vec<&str> = data.split_whitespace().collect();
-        let name = String::from(info_vec[0]);
-        let energy = info_vec[1].parse::<u64>().unwrap();
-        let power = info_vec[2].parse::<u64>().unwrap();
-        Self {
-            name: name,
-            energy: energy,
-            power: power,
+        // yes / no answers in portuguese and english
+        match data.trim().to_lowercase().as_ref() {
+            "s" | "sim" | "y" | "yes" => return PlayerInput::Answer(true),
+            "n" | "nao" | "não" | "no" => return PlayerInput::Answer(false),
+            _ => (),
+        }
+        if data.split_whitespace().count() == 1 {
+            return PlayerInput::Choice(String::from(data.trim()));
+        }
+        match PlayerInfo::from_str(data) {
+            Ok(info) => PlayerInput::Info(info),
+            Err(e) => PlayerInput::Invalid(format!("{}", e)),
         }
     }
 }
 
-pub struct GetPlayerInfoMsg;
+/// Asks for the next player info, waiting up to the timeout when set
+pub struct GetPlayerInfoMsg {
+    pub timeout: Option<Duration>,
+}
 
 /// Turn GetPlayerInfo messaging enabled
 impl Message for GetPlayerInfoMsg {
-    type Result = Result<PlayerInfo, Error>;
+    type Result = Result<PlayerInput, Error>;
 }
 
 pub struct StartBattleMsg;
-/// Turn GetPlayerInfo messaging enabled
+/// Turn StartBattle messaging enabled, answered with the new game id
 impl Message for StartBattleMsg {
-    type Result = Result<(), Error>;
+    type Result = Result<u64, Error>;
 }
 
+/// Asks the player on turn for it's action, answered with the action or
+/// None when the player doesn't answer in time
+pub struct AskActionMsg {
+    /// Player on turn, only it's input answers
+    pub player: String,
+    pub prompt: ClientAction,
+    pub timeout: Duration,
+}
+
+/// AskAction messaging enabled
+impl Message for AskActionMsg {
+    type Result = Result<Option<TurnAction>, Error>;
+}
+
+/// Asks the players for a rematch, waiting up to the timeout when set
+pub struct RematchMsg {
+    pub timeout: Option<Duration>,
+}
+
+/// Rematch messaging enabled, true when players want to play again and
+/// None when nobody answered in time
+impl Message for RematchMsg {
+    type Result = Result<Option<bool>, Error>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BattleTurnMsg {
-    pub range: Vec<u8>,
+    pub attacks: Vec<Attack>,
     pub info: Vec<PlayerInfo>,
     pub hp: Vec<u64>,
-    pub turn: usize,    
+    pub turn: usize,
+    /// Turn counter since the battle start, the first turn is 1
+    pub number: u64,
+    /// Configured targeting, the rule set picks the target when None
+    #[serde(default)]
+    pub targeting: Option<Targeting>,
+    /// Target chosen by the attacker
+    #[serde(default)]
+    pub target: Option<usize>,
+    /// Action of the attacker, an automatic attack when None
+    #[serde(default)]
+    pub action: Option<TurnAction>,
+    /// Players defending until their next turn
+    #[serde(default)]
+    pub defending: Vec<bool>,
+    /// Configured turn order, the rule set decides when None
+    #[serde(default)]
+    pub turn_order: Option<TurnOrder>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Status effects on every player
+    #[serde(default)]
+    pub effects: Vec<Vec<Effect>>,
+    /// Ability catalog the abilities of the players are looked up on
+    #[serde(default)]
+    pub abilities: Vec<Ability>,
+    /// Mana of every player, full pools when empty
+    #[serde(default)]
+    pub mana: Vec<u64>,
+    /// Turns every player waits to use each ability of the catalog again
+    #[serde(default)]
+    pub cooldowns: Vec<Vec<u64>>,
+    /// Item catalog the items of the players are looked up on
+    #[serde(default)]
+    pub items: Vec<Item>,
+    /// Items every player has left to consume, the consumables it carries
+    /// when empty
+    #[serde(default)]
+    pub inventory: Vec<Vec<String>>,
+}
+
+impl BattleTurnMsg {
+    /// First turn of a battle between the players at full HP, seat 0
+    /// attacks first without any effect, ability or item
+    pub fn new(attacks: Vec<Attack>, info: Vec<PlayerInfo>, hp: Vec<u64>) -> Self {
+        BattleTurnMsg {
+            attacks,
+            info,
+            hp,
+            turn: 0,
+            number: 1,
+            targeting: None,
+            target: None,
+            action: None,
+            defending: Vec::new(),
+            turn_order: None,
+            schedule: Schedule::default(),
+            effects: Vec::new(),
+            abilities: Vec::new(),
+            mana: Vec::new(),
+            cooldowns: Vec::new(),
+            items: Vec::new(),
+            inventory: Vec::new(),
+        }
+    }
 }
 
 /// BattleTurn messaging enabled
@@ -145,10 +1339,25 @@ impl Message for BattleTurnMsg {
     type Result = Result<TurnResultMsg, Error>;
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct TurnResultMsg {
     pub hp: Vec<u64>,    
     pub next_turn: usize,
     pub winner: Option<(usize, u64)>,
+    /// Players defending until their next turn
+    #[serde(default)]
+    pub defending: Vec<bool>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Status effects on every player
+    #[serde(default)]
+    pub effects: Vec<Vec<Effect>>,
+    #[serde(default)]
+    pub mana: Vec<u64>,
+    #[serde(default)]
+    pub cooldowns: Vec<Vec<u64>>,
+    #[serde(default)]
+    pub inventory: Vec<Vec<String>>,
 }
 
 pub struct BattleWarmUpMsg {
@@ -161,16 +1370,169 @@ impl Message for BattleWarmUpMsg {
     type Result = Result<bool, Error>;
 }
 
-pub struct BattleAnnounceMsg(pub Vec<String>);
+/// The battle is announced with it's first turn, answered with the turn
+/// opened by the turn order and it's schedule
+pub struct BattleAnnounceMsg(pub BattleTurnMsg);
 
 /// BattleAnnounce messaging enabled
 impl Message for BattleAnnounceMsg {
-    type Result = Result<(), Error>;
+    type Result = Result<BattleTurnMsg, Error>;
 }
 
-pub struct WinnerMsg(pub String, pub u64);
+/// The battle is over, winner index with the players and their HP
+pub struct WinnerMsg {
+    pub winner: usize,
+    pub info: Vec<PlayerInfo>,
+    pub hp: Vec<u64>,
+}
 
 /// WinnerMsg messaging enabled
 impl Message for WinnerMsg {
     type Result = Result<(), Error>;
 }
+
+/// Everything emitted during a game that is saved to a replay file,
+/// one event per line, in the same order it happened
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum ReplayEvent {
+    Action(ClientAction),
+    Dice(u8),
+    Turn(BattleTurnMsg, TurnResultMsg),
+}
+
+/// ReplayEvent messaging enabled
+impl Message for ReplayEvent {
+    type Result = Result<(), Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classic_attacks_test() {
+        // the original rules, lucky and critical on top of the standard damage
+        let standard = |power| power / 3;
+        let rules: Vec<Box<Fn(u64) -> u64>> = vec![
+            Box::new(|_| 0),
+            Box::new(standard),
+            Box::new(move |power| standard(power) + standard(power) / 5),
+            Box::new(move |power| 2 * standard(power)),
+        ];
+        let attacks = classic_attacks();
+        for power in (0..10_000).chain(u64::max_value() / 2 - 10..u64::max_value() / 2) {
+            for (attack, rule) in attacks.iter().zip(&rules) {
+                assert_eq!(attack.damage(power), rule(power), "{} {}", attack.name, power);
+            }
+        }
+    }
+
+    #[test]
+    fn attack_damage_saturates_test() {
+        let mut attack = Attack::new("Huge", 0, 100, 3, 2);
+        assert_eq!(attack.damage(u64::max_value() / 2), u64::max_value() / 4 * 3 + 1);
+        assert_eq!(attack.damage(u64::max_value()), u64::max_value());
+        attack.mul = 1;
+        attack.div = 1;
+        attack.bonus = 10;
+        assert_eq!(attack.damage(u64::max_value() - 1), u64::max_value());
+        // abilities saturate the same
+        let mut ability = classic_abilities().swap_remove(0);
+        ability.mul = 3;
+        ability.div = 2;
+        ability.bonus = 10;
+        assert_eq!(ability.damage(u64::max_value()), u64::max_value());
+        assert_eq!(ability.damage(10), 25);
+    }
+
+    #[test]
+    fn player_input_test() {
+        match PlayerInput::from("A 100 50") {
+            PlayerInput::Info(p) => {
+                assert_eq!(p.name, "A");
+                assert_eq!(p.energy, 100);
+                assert_eq!(p.power, 50);
+            }
+            i => panic!("{:?}", i),
+        }
+        match PlayerInput::from(" Sim ") {
+            PlayerInput::Answer(a) => assert!(a),
+            i => panic!("{:?}", i),
+        }
+        match PlayerInput::from("n") {
+            PlayerInput::Answer(a) => assert!(!a),
+            i => panic!("{:?}", i),
+        }
+        match PlayerInput::from("A 100 50 lowest-hp") {
+            PlayerInput::Info(p) => assert_eq!(p.targeting, Some(Targeting::LowestHp)),
+            i => panic!("{:?}", i),
+        }
+        match PlayerInput::from("A 100 50 @red random speed=12 defense=5 max_hp=150") {
+            PlayerInput::Info(p) => {
+                assert_eq!(p.team, Some(String::from("red")));
+                assert_eq!(p.targeting, Some(Targeting::Random));
+                assert_eq!(p.speed(), 12);
+                assert_eq!(p.attributes.defense, 5);
+                assert_eq!(p.max_hp(), 150);
+            }
+            i => panic!("{:?}", i),
+        }
+        match PlayerInput::from(" B ") {
+            PlayerInput::Choice(c) => assert_eq!(c, "B"),
+            i => panic!("{:?}", i),
+        }
+        assert_eq!(TurnAction::from("Defend"), TurnAction::Defend);
+        assert_eq!(TurnAction::from("pular"), TurnAction::Heal);
+        assert_eq!(TurnAction::from("a"), TurnAction::Attack(None));
+        assert_eq!(TurnAction::from("C"), TurnAction::Attack(Some(String::from("C"))));
+        let invalid = [
+            "A 100",
+            "A x 100",
+            "A 100 -5",
+            "A 100 50 sideways",
+            "A 100 50 random random",
+            "A 100 50 speed=fast",
+            "A 100 50 luck=7",
+            "A 100 50 evasion=101",
+            "A 100 50 level=2",
+            "",
+        ];
+        for line in &invalid {
+            match PlayerInput::from(*line) {
+                PlayerInput::Invalid(_) => (),
+                i => panic!("{:?} from {}", i, line),
+            }
+        }
+    }
+
+    #[test]
+    fn character_class_test() {
+        let classes = classic_classes();
+        let line = |l: &str| match PlayerInput::from(l) {
+            PlayerInput::Info(p) => expand_class(&classes, p),
+            i => panic!("{:?}", i),
+        };
+        let alice = line("Alice warrior").unwrap();
+        assert_eq!((alice.energy, alice.power, alice.attributes.defense), (140, 45, 4));
+        assert_eq!(alice.level, Some(1));
+        let bob = line("Bob rogue level=3 @red defense=2 random").unwrap();
+        assert_eq!((bob.energy, bob.power, bob.speed()), (118, 65, 16));
+        assert_eq!((bob.attributes.defense, bob.attributes.evasion), (2, 8));
+        // entering the default value still wins over the class
+        let eve = line("Eve rogue evasion=0 mana=0").unwrap();
+        assert_eq!((eve.attributes.evasion, eve.attributes.mana), (0, 0));
+        // levels stop at MAX_LEVEL and growth never overflows
+        let frank = line("Frank warrior level=18446744073709551615").unwrap();
+        assert_eq!(frank.level, Some(MAX_LEVEL));
+        assert_eq!(frank.energy, 140 + 14 * (MAX_LEVEL - 1));
+        let mut titan = classes[0].clone();
+        titan.growth.energy = u64::max_value();
+        assert_eq!(titan.expand(&frank).energy, u64::max_value());
+        assert_eq!(bob.targeting, Some(Targeting::Random));
+        assert_eq!(bob.team, Some(String::from("red")));
+        assert_eq!(line("Carol MAGE").unwrap().targeting, Some(Targeting::LowestHp));
+        // expanded players are kept as they are
+        assert_eq!(expand_class(&classes, bob.clone()).unwrap().power, 65);
+        assert!(line("Dave bard").is_err());
+    }
+}
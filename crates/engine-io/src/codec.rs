@@ -1,28 +1,62 @@
-
 use bytes::BytesMut;
 use tokio_codec::{Decoder, Encoder, LinesCodec};
-use core::{ClientAction, PlayerInfo};
+use core::{ClientAction, PlayerInput};
+use i18n::Catalog;
+use serde_json;
 use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
 
 // Encoding Decoding protocol for PlayerInfo data
 pub struct PlayerInfoCodec(pub LinesCodec);
 
 impl Decoder for PlayerInfoCodec {
-  type Item = PlayerInfo;
+  type Item = PlayerInput;
   type Error = io::Error;
 
   // uses an inner LineCodec to convert bytes to String and later convert
-  // from string to PlayerInfo
-  fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<PlayerInfo>> {
+  // from string to PlayerInput, malformed lines are not an io error
+  fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<PlayerInput>> {
     self
       .0
       .decode(buf)
-      // PlayerInfo::from call converter defined in core 
-      .map(|ol| ol.map(|l| PlayerInfo::from(l.as_ref())))
+      // PlayerInput::from call the PlayerInfo FromStr defined in core
+      .map(|ol| ol.map(|l| PlayerInput::from(l.as_ref())))
   }
 }
 
-pub struct ClientActionCodec(pub LinesCodec);
+/// Output format of ClientAction, text is the human readable display
+/// and jsonl is one serialized ClientAction per line for scripts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  Text,
+  JsonLines,
+}
+
+impl FromStr for OutputFormat {
+  type Err = io::Error;
+
+  fn from_str(data: &str) -> Result<OutputFormat, io::Error> {
+    match data {
+      "text" => Ok(OutputFormat::Text),
+      "jsonl" => Ok(OutputFormat::JsonLines),
+      f => Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Unknown output format {}", f),
+      )),
+    }
+  }
+}
+
+// Text output is rendered through the message catalog of the user language
+#[derive(Clone)]
+pub struct ClientActionCodec(pub LinesCodec, pub OutputFormat, pub Arc<Catalog>);
+
+impl ClientActionCodec {
+  pub fn new(format: OutputFormat, catalog: Arc<Catalog>) -> Self {
+    ClientActionCodec(LinesCodec::new(), format, catalog)
+  }
+}
 
 impl Encoder for ClientActionCodec {
   type Item = ClientAction;
@@ -30,7 +64,11 @@ impl Encoder for ClientActionCodec {
 
   // encode to String and later from String to bytes using the inner LinesCodec
   fn encode(&mut self, item: ClientAction, buf: &mut BytesMut) -> Result<(), io::Error> {
-    let line: String = format!("{}", item);
+    let line: String = match self.1 {
+      OutputFormat::Text => self.2.render(&item),
+      OutputFormat::JsonLines => serde_json::to_string(&item)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+    };
     self.0.encode(line, buf)
   }
-}
\ No newline at end of file
+}
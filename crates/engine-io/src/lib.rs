@@ -1,5 +1,7 @@
 //! * Engine-io
-//! This is the library providing connectivity with stdin stdout transport.
+//! This is the library providing connectivity with stdin stdout transport,
+//! with tcp sockets for multiplayer battles and with websockets for web
+//! frontends.
 //! It is based on Actor model https://en.wikipedia.org/wiki/Actor_model
 //! leveraging years of battle tested use with several languages and mostly
 //! with erlang in the telecomunication industry proving to handle millions
@@ -8,6 +10,7 @@
 #![cfg_attr(feature = "flame_it", plugin(flamer))]
 
 extern crate actix;
+extern crate actix_web;
 extern crate bytes;
 extern crate core;
 extern crate crossbeam_channel as channel;
@@ -15,43 +18,256 @@ extern crate failure;
 #[cfg(feature = "flame_it")]
 extern crate flame;
 extern crate futures;
+extern crate i18n;
+extern crate serde_json;
 extern crate tokio_codec;
 #[cfg(not(windows))]
 extern crate tokio_file_unix as ufs;
 extern crate tokio_io;
 extern crate tokio_reactor;
 extern crate tokio_stdin_stdout;
+extern crate tokio_tcp;
 #[cfg(windows)]
 extern crate winapi;
 
 mod codec;
 mod pipe;
+pub mod replay;
+pub mod tcp;
+pub mod ws;
+
+pub use codec::{ClientActionCodec, OutputFormat};
 
 use actix::io::{FramedWrite, WriteHandler};
-use actix::{Actor, AsyncContext, Context, Handler, StreamHandler};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, Recipient, StreamHandler};
 use channel::Sender;
-use core::{ClientAction, PlayerInfo};
+use core::{AskActionMsg, ClientAction, PlayerInput, ReplayEvent, TurnAction};
 use failure::Error;
+use futures::sync::oneshot;
+use futures::Future;
 use std::io;
+use std::time::Instant;
 use tokio_codec::FramedRead;
 use tokio_io::AsyncWrite;
 
-// Main Stdin Stdout Actor placeholder
+// Main Stdin Stdout Actor placeholder, it's also the hub broadcasting
+// every ClientAction to the connected sessions
 pub struct EnginePipeIo {
+  pub stdio: bool,
+  pub codec: ClientActionCodec,
   pub in_pipe: bool,
   pub out_pipe: bool,
-  pub sender: Sender<PlayerInfo>,
+  pub sender: Sender<PlayerInput>,
   pub writer:
     Option<actix::io::FramedWrite<pipe::ImplAsyncWriteStream, codec::ClientActionCodec>>,
   pub writer_pipe:
     Option<actix::io::FramedWrite<tokio_stdin_stdout::SendableStdout, codec::ClientActionCodec>>,
+  pub recorder: Option<replay::Recorder>,
+  pub sessions: Vec<Session>,
+  /// Players entered by the sessions the engine didn't check yet, in the
+  /// order they were sent to bpm
+  pub entering: Vec<Entering>,
+  pub action: Option<PendingAction>,
+  /// Attacker asked to choose it's target, only it's session answers
+  pub target: Option<String>,
+  /// Answer of a CloseMsg, sent when the writer flushed every ClientAction
+  pub flushed: Option<oneshot::Sender<()>>,
+}
+
+/// Player entered by a session, waiting for the engine to check it
+pub struct Entering {
+  pub player: String,
+  pub session: Recipient<Reject>,
+}
+
+/// Action request waiting for the player on turn
+pub struct PendingAction {
+  /// Player on turn, the sessions of other players can't answer
+  pub player: String,
+  pub deadline: Instant,
+  pub reply: oneshot::Sender<TurnAction>,
+}
+
+impl EnginePipeIo {
+  /// IO hub of a network game, without stdin and stdout, feeding the
+  /// player input of it's sessions to the sender
+  pub fn new(codec: ClientActionCodec, sender: Sender<PlayerInput>) -> Self {
+    EnginePipeIo {
+      stdio: false,
+      codec,
+      in_pipe: false,
+      out_pipe: false,
+      sender,
+      writer: None,
+      writer_pipe: None,
+      recorder: None,
+      sessions: Vec::new(),
+      entering: Vec::new(),
+      action: None,
+      target: None,
+      flushed: None,
+    }
+  }
+
+  // player input answers the pending action request, otherwise it goes
+  // to the bpm input channel. Input of the player sessions only answers
+  // for their own player, local input (player None) plays for everybody.
+  // Any other input while waiting for the action, or a target chosen by
+  // another player than the attacker, is answered with a notice of who
+  // the game is waiting for
+  fn route(&mut self, input: PlayerInput, player: Option<&str>) -> Option<ClientAction> {
+    let on_turn = |name: &str| player.map_or(true, |p| p == name);
+    let pending = match self.action.take() {
+      Some(pending) => pending,
+      None => {
+        if let PlayerInput::Choice(_) = input {
+          if let Some(attacker) = self.target.take() {
+            if !on_turn(&attacker) {
+              let notice = ClientAction::WaitingAction(attacker.clone());
+              self.target = Some(attacker);
+              return Some(notice);
+            }
+          }
+        }
+        self.sender.send(input);
+        return None;
+      }
+    };
+    match input {
+      PlayerInput::Choice(ref word) if on_turn(&pending.player) => {
+        let _ = pending.reply.send(TurnAction::from(word.as_str()));
+        None
+      }
+      _ => {
+        let notice = ClientAction::WaitingAction(pending.player.clone());
+        self.action = Some(pending);
+        Some(notice)
+      }
+    }
+  }
+
+  // only to the local output
+  fn write(&mut self, msg: ClientAction) {
+    if let Some(ref mut w) = self.writer {
+      w.write(msg);
+    } else if let Some(ref mut w) = self.writer_pipe {
+      w.write(msg);
+    }
+  }
+
+  fn broadcast(&mut self, msg: ClientAction) {
+    // the game waits for the target of an attacker until it broadcasts
+    // anything else
+    self.target = match msg {
+      ClientAction::AskTarget {
+        ref attacker_name, ..
+      } => Some(attacker_name.clone()),
+      _ => None,
+    };
+    if let Some(ref mut r) = self.recorder {
+      // a failing recording should not stop the battle
+      let _ = r.record(&ReplayEvent::Action(msg.clone()));
+    }
+    self.write(msg.clone());
+    // closed sessions are dropped from the broadcast
+    self.sessions.retain(|s| s.actions.do_send(msg.clone()).is_ok());
+  }
+}
+
+/// A network session subscribed to the broadcast of a game
+pub struct Session {
+  pub actions: Recipient<ClientAction>,
+  pub disconnect: Recipient<Disconnect>,
+}
+
+/// A session subscribing to the ClientAction broadcast
+pub struct Connect(pub Session);
+
+/// Turn Connect messaging enabled
+impl Message for Connect {
+  type Result = ();
+}
+
+/// Player input of a network session, with the player the session
+/// entered when it has one
+pub struct SessionInput {
+  pub player: Option<String>,
+  pub input: PlayerInput,
+  /// Where the notices about the input go
+  pub session: Recipient<ClientAction>,
+  /// Where a rejected player goes
+  pub reject: Recipient<Reject>,
+}
+
+/// Turn SessionInput messaging enabled
+impl Message for SessionInput {
+  type Result = ();
+}
 
+/// The engine checked a player entered on the game, with the reason it
+/// was rejected. A rejected player is asked again
+pub struct EnterMsg {
+  pub player: String,
+  pub rejected: Option<String>,
 }
 
-impl StreamHandler<PlayerInfo, io::Error> for EnginePipeIo {
-  fn handle(&mut self, item: PlayerInfo, _ctx: &mut Context<EnginePipeIo>) {
+/// Turn Enter messaging enabled
+impl Message for EnterMsg {
+  type Result = ();
+}
+
+/// The player entered by a session was rejected or never reached the
+/// game, the session may enter another one
+pub struct Reject;
+
+/// Turn Reject messaging enabled
+impl Message for Reject {
+  type Result = ();
+}
+
+/// Seat taken by a network session on a hosted game, the game IO hub
+/// to subscribe and to send it's player input, and the lobby to give
+/// the seat back
+#[derive(Clone)]
+pub struct Seat {
+  pub game: u64,
+  pub hub: Addr<EnginePipeIo>,
+  pub lobby: Recipient<LeaveMsg>,
+}
+
+/// A network session asking for a seat on a game
+pub struct JoinMsg;
+
+/// Turn Join messaging enabled
+impl Message for JoinMsg {
+  type Result = Result<Seat, Error>;
+}
+
+/// A network session leaving it's game before entering a player, the
+/// seat is free for the next session
+pub struct LeaveMsg {
+  pub game: u64,
+}
+
+/// Turn Leave messaging enabled
+impl Message for LeaveMsg {
+  type Result = ();
+}
+
+/// The game of a session is over, the session is closed
+pub struct Disconnect;
+
+/// Turn Disconnect messaging enabled
+impl Message for Disconnect {
+  type Result = ();
+}
+
+impl StreamHandler<PlayerInput, io::Error> for EnginePipeIo {
+  fn handle(&mut self, item: PlayerInput, _ctx: &mut Context<EnginePipeIo>) {
     // println!("Sending: \t {:?}", item);
-    self.sender.send(item);
+    if let Some(notice) = self.route(item, None) {
+      self.write(notice);
+    }
   }
 
   // fn error(&mut self, err: io::Error, _ctx: &mut Self::Context) -> Running {
@@ -72,6 +288,10 @@ impl Actor for EnginePipeIo {
   type Context = Context<Self>;
 
   fn started(&mut self, ctx: &mut Self::Context) {
+    // network only connectors have no stdin and stdout
+    if !self.stdio {
+      return;
+    }
     if self.in_pipe {
       let stdin = tokio_stdin_stdout::stdin(0);
       ctx.add_stream(FramedRead::new(
@@ -89,7 +309,7 @@ impl Actor for EnginePipeIo {
       let stdout = tokio_stdin_stdout::stdout(0).make_sendable();
       let f_stdout = FramedWrite::new(
         stdout,
-        codec::ClientActionCodec(tokio_codec::LinesCodec::new()),
+        self.codec.clone(),
         ctx,
       );
       self.writer_pipe = Some(f_stdout);
@@ -97,7 +317,7 @@ impl Actor for EnginePipeIo {
       let stdout = pipe::stdout_stream().unwrap();
       let f_stdout = FramedWrite::new(
         stdout,
-        codec::ClientActionCodec(tokio_codec::LinesCodec::new()),
+        self.codec.clone(),
         ctx,
       );
       self.writer = Some(f_stdout);      
@@ -105,7 +325,15 @@ impl Actor for EnginePipeIo {
   }
 }
 
-impl WriteHandler<io::Error> for EnginePipeIo {}
+impl WriteHandler<io::Error> for EnginePipeIo {
+  // a closed writer is done after writing everything buffered, the
+  // actor keeps running for the sessions
+  fn finished(&mut self, _ctx: &mut Self::Context) {
+    if let Some(flushed) = self.flushed.take() {
+      let _ = flushed.send(());
+    }
+  }
+}
 
 /// Message handling for type ClientAction
 impl Handler<ClientAction> for EnginePipeIo {
@@ -115,12 +343,160 @@ impl Handler<ClientAction> for EnginePipeIo {
   // MessageBox
   #[cfg_attr(feature = "flame_it", flame)]
   fn handle(&mut self, msg: ClientAction, _: &mut Self::Context) -> Self::Result {
-    if let Some(ref mut w) = self.writer {
-      w.write(msg);
+    self.broadcast(msg);
+    Ok(())
+  }
+}
+
+/// Message handling for type PlayerInput, local input playing for
+/// every player like stdin
+impl Handler<PlayerInput> for EnginePipeIo {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: PlayerInput, _: &mut Self::Context) {
+    if let Some(notice) = self.route(msg, None) {
+      self.write(notice);
+    }
+  }
+}
+
+/// Message handling for type SessionInput
+impl Handler<SessionInput> for EnginePipeIo {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: SessionInput, _: &mut Self::Context) {
+    // a session without a player answers for nobody
+    let player = msg.player.unwrap_or_default();
+    let entering = match msg.input {
+      PlayerInput::Info(ref info) => Some(info.name.clone()),
+      _ => None,
+    };
+    match self.route(msg.input, Some(player.as_str())) {
+      Some(notice) => {
+        // a player not sent to bpm is entered again
+        if entering.is_some() {
+          let _ = msg.reject.do_send(Reject);
+        }
+        let _ = msg.session.do_send(notice);
+      }
+      None => {
+        if let Some(player) = entering {
+          self.entering.push(Entering {
+            player,
+            session: msg.reject,
+          });
+        }
+      }
+    }
+  }
+}
+
+/// Message handling for type EnterMsg
+impl Handler<EnterMsg> for EnginePipeIo {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: EnterMsg, _: &mut Self::Context) {
+    // bpm reads the players in the order they were sent, the first one
+    // with the name is the one checked. Local players have no session
+    if let Some(i) = self.entering.iter().position(|e| e.player == msg.player) {
+      let entering = self.entering.remove(i);
+      if msg.rejected.is_some() {
+        let _ = entering.session.do_send(Reject);
+      }
+    }
+    if let Some(reason) = msg.rejected {
+      self.broadcast(ClientAction::Message(reason));
+    }
+  }
+}
+
+/// Message handling for type AskActionMsg
+impl Handler<AskActionMsg> for EnginePipeIo {
+  type Result = Box<Future<Item = Option<TurnAction>, Error = Error>>;
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: AskActionMsg, ctx: &mut Self::Context) -> Self::Result {
+    let (reply, answer) = oneshot::channel();
+    let deadline = Instant::now() + msg.timeout;
+    // a request still pending is cancelled and answered with None
+    self.action = Some(PendingAction {
+      player: msg.player,
+      deadline,
+      reply,
+    });
+    self.broadcast(msg.prompt);
+    // when time is up the request is dropped and answered with None,
+    // a newer request is still waiting for it's own deadline
+    ctx.run_later(msg.timeout, |act, _ctx| {
+      if act.action.as_ref().map_or(false, |p| p.deadline <= Instant::now()) {
+        act.action = None;
+      }
+    });
+    Box::new(answer.then(|a| -> Result<Option<TurnAction>, Error> { Ok(a.ok()) }))
+  }
+}
+
+/// Closes the stdout writer and the sessions of a game, answered once
+/// every ClientAction written so far reached the output
+pub struct CloseMsg;
+
+/// Turn Close messaging enabled
+impl Message for CloseMsg {
+  type Result = Result<(), Error>;
+}
+
+/// Message handling for type CloseMsg
+impl Handler<CloseMsg> for EnginePipeIo {
+  type Result = Box<Future<Item = (), Error = Error>>;
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, _msg: CloseMsg, _: &mut Self::Context) -> Self::Result {
+    let (flushed, done) = oneshot::channel();
+    let closing = if let Some(ref mut w) = self.writer {
+      w.close();
+      true
     } else if let Some(ref mut w) = self.writer_pipe {
-      w.write(msg);
+      w.close();
+      true
+    } else {
+      false
+    };
+    if closing {
+      self.flushed = Some(flushed);
+    } else {
+      // network only connectors have nothing to flush
+      let _ = flushed.send(());
+    }
+    for s in self.sessions.drain(..) {
+      let _ = s.disconnect.do_send(Disconnect);
+    }
+    // a writer failing to flush stops the actor and cancels the answer
+    Box::new(done.then(|_| -> Result<(), Error> { Ok(()) }))
+  }
+}
+
+/// Message handling for type Connect
+impl Handler<Connect> for EnginePipeIo {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: Connect, _: &mut Self::Context) {
+    self.sessions.push(msg.0);
+  }
+}
+
+/// Message handling for type ReplayEvent
+impl Handler<ReplayEvent> for EnginePipeIo {
+  type Result = Result<(), Error>;
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: ReplayEvent, _: &mut Self::Context) -> Self::Result {
+    if let Some(ref mut r) = self.recorder {
+      r.record(&msg)?;
     }
-    // println!("{}", msg);
     Ok(())
   }
 }
@@ -142,8 +518,142 @@ impl Handler<ClientAction> for EnginePipeIo {
 // Decide not go further testing here due to simplicity of functionality
 #[cfg(test)]
 mod tests {
+  use actix::{Actor, Arbiter, System};
+  use bytes::BytesMut;
+  use channel;
+  use codec::{ClientActionCodec, OutputFormat};
+  use core::{AskActionMsg, AttackType, ClientAction, PlayerInput, TurnAction};
+  use futures::sync::oneshot;
+  use futures::Future;
+  use i18n::Catalog;
+  use std::sync::{Arc, Mutex};
+  use std::time::{Duration, Instant};
+  use tokio_codec::Encoder;
+  use {EnginePipeIo, PendingAction};
+
+  fn hub() -> (EnginePipeIo, channel::Receiver<PlayerInput>) {
+    let catalog = Arc::new(Catalog::load("en-US", None).unwrap());
+    let (sender, input) = channel::bounded(10);
+    let codec = ClientActionCodec::new(OutputFormat::Text, catalog);
+    (EnginePipeIo::new(codec, sender), input)
+  }
+
+  fn pending(hub: &mut EnginePipeIo, player: &str) -> oneshot::Receiver<TurnAction> {
+    let (reply, answer) = oneshot::channel();
+    hub.action = Some(PendingAction {
+      player: String::from(player),
+      deadline: Instant::now(),
+      reply,
+    });
+    answer
+  }
+
+  fn ask(timeout: Duration) -> AskActionMsg {
+    AskActionMsg {
+      player: String::from("A"),
+      prompt: ClientAction::AskAction {
+        attacker_name: String::from("A"),
+        targets: vec![String::from("B")],
+        timeout: timeout.as_secs(),
+      },
+      timeout,
+    }
+  }
+
   #[test]
   fn it_works() {
     assert_eq!(2 + 2, 4);
   }
+
+  #[test]
+  fn route_test() {
+    let (mut hub, input) = hub();
+    let choice = |word: &str| PlayerInput::Choice(String::from(word));
+    // without an action request the input goes to bpm
+    assert!(hub.route(choice("B"), Some("A")).is_none());
+    match input.try_recv() {
+      Some(PlayerInput::Choice(c)) => assert_eq!(c, "B"),
+      i => panic!("{:?}", i),
+    }
+    // the sessions of other players, or without a player, are told who
+    // is on turn and so is anything else than an action
+    let answer = pending(&mut hub, "A");
+    let others = vec![(choice("d"), "B"), (choice("d"), ""), (PlayerInput::Answer(true), "A")];
+    for (line, player) in others {
+      match hub.route(line, Some(player)) {
+        Some(ClientAction::WaitingAction(p)) => assert_eq!(p, "A"),
+        a => panic!("{:?}", a),
+      }
+    }
+    // the session of the player on turn answers
+    assert!(hub.route(choice("defend"), Some("A")).is_none());
+    assert_eq!(answer.wait().unwrap(), TurnAction::Defend);
+    assert!(hub.action.is_none());
+    // local input plays for every player
+    let answer = pending(&mut hub, "A");
+    assert!(hub.route(choice("heal"), None).is_none());
+    assert_eq!(answer.wait().unwrap(), TurnAction::Heal);
+    assert!(input.try_recv().is_none());
+    // a chosen target only comes from the attacker
+    hub.broadcast(ClientAction::AskTarget {
+      attacker_name: String::from("A"),
+      targets: vec![String::from("B"), String::from("C")],
+    });
+    match hub.route(choice("C"), Some("B")) {
+      Some(ClientAction::WaitingAction(p)) => assert_eq!(p, "A"),
+      a => panic!("{:?}", a),
+    }
+    assert!(input.try_recv().is_none());
+    assert!(hub.route(choice("C"), Some("A")).is_none());
+    match input.try_recv() {
+      Some(PlayerInput::Choice(c)) => assert_eq!(c, "C"),
+      i => panic!("{:?}", i),
+    }
+    assert!(hub.target.is_none());
+  }
+
+  #[test]
+  fn ask_action_test() {
+    let sys = System::new("ask-action-test");
+    let (hub, _input) = hub();
+    let hub = hub.start();
+    let answers = Arc::new(Mutex::new(Vec::new()));
+    let record = answers.clone();
+    // a newer request cancels the pending one, a request nobody answers
+    // in time is answered with None
+    let cancelled = hub.send(ask(Duration::from_secs(3600)));
+    let answered = hub.send(ask(Duration::from_secs(3600)));
+    hub.do_send(PlayerInput::Choice(String::from("defend")));
+    let timed_out = hub.send(ask(Duration::from_millis(10)));
+    Arbiter::spawn(
+      cancelled
+        .join3(answered, timed_out)
+        .map(move |(c, a, t)| {
+          *record.lock().unwrap() = vec![c.unwrap(), a.unwrap(), t.unwrap()];
+          System::current().stop();
+        }).map_err(|_| ()),
+    );
+    let _ = sys.run();
+    assert_eq!(*answers.lock().unwrap(), vec![None, Some(TurnAction::Defend), None]);
+  }
+
+  #[test]
+  fn jsonl_output_test() {
+    let catalog = Arc::new(Catalog::load("en-US", None).unwrap());
+    let mut codec = ClientActionCodec::new(OutputFormat::JsonLines, catalog);
+    let mut buf = BytesMut::new();
+    let action = ClientAction::AttackResult {
+      turn: 3,
+      attack: AttackType(String::from("Lucky")),
+      key: String::from("attack-lucky"),
+      damage: 39,
+      defender: 1,
+      hp: 61,
+    };
+    codec.encode(action, &mut buf).unwrap();
+    assert_eq!(
+      &buf[..],
+      &b"{\"AttackResult\":{\"turn\":3,\"attack\":\"Lucky\",\"key\":\"attack-lucky\",\"damage\":39,\"defender\":1,\"hp\":61}}\n"[..]
+    );
+  }
 }
@@ -0,0 +1,68 @@
+use actix::Addr;
+use core::ReplayEvent;
+use failure::Error;
+use futures::{stream, Future, Stream};
+use serde_json;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use EnginePipeIo;
+
+// Replay file writer, every event is a json line flushed right away
+// so the recording survives even when the process crashes
+pub struct Recorder(BufWriter<File>);
+
+impl Recorder {
+  pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+    Ok(Recorder(BufWriter::new(File::create(path)?)))
+  }
+
+  pub fn record(&mut self, event: &ReplayEvent) -> Result<(), Error> {
+    serde_json::to_writer(&mut self.0, event)?;
+    self.0.write_all(b"\n")?;
+    self.0.flush()?;
+    Ok(())
+  }
+}
+
+/// Replay file name for a game, time stamped so a new battle with the
+/// same seed does not overwrite an older recording, games hosted at the
+/// same time are told apart by their id
+pub fn file_name(seed: u64, game: u64) -> String {
+  let secs = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  format!("replay-{}-{}-{}.jsonl", seed, secs, game)
+}
+
+/// Loads every event recorded on a replay file
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<ReplayEvent>, Error> {
+  let reader = BufReader::new(File::open(path)?);
+  let mut events = Vec::new();
+  for line in reader.lines() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    events.push(serde_json::from_str(&line)?);
+  }
+  Ok(events)
+}
+
+/// Drives the IO connector with the recorded client actions, in the same
+/// order they were emitted, instead of playing with the bpm rules
+pub fn playback(
+  events: Vec<ReplayEvent>,
+  io: Addr<EnginePipeIo>,
+) -> Box<Future<Item = (), Error = Error>> {
+  let actions = events.into_iter().filter_map(|e| match e {
+    ReplayEvent::Action(a) => Some(a),
+    _ => None,
+  });
+  Box::new(
+    stream::iter_ok::<_, Error>(actions)
+      .for_each(move |a| io.send(a).map_err(Into::into).and_then(|r| r)),
+  )
+}
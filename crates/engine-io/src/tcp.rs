@@ -0,0 +1,203 @@
+use actix::io::{FramedWrite, WriteHandler};
+use actix::{
+  fut, Actor, ActorContext, ActorFuture, AsyncContext, Context, Handler, Message, Recipient,
+  StreamHandler, WrapFuture,
+};
+use codec::{ClientActionCodec, PlayerInfoCodec};
+use core::{ClientAction, PlayerInput};
+use failure::Error;
+use futures::Stream;
+use std::io;
+use std::net::SocketAddr;
+use tokio_codec::{FramedRead, LinesCodec};
+use tokio_io::io::WriteHalf;
+use tokio_io::AsyncRead;
+use tokio_tcp::{TcpListener, TcpStream};
+use {Connect, Disconnect, JoinMsg, LeaveMsg, Reject, Seat, Session, SessionInput};
+
+/// New socket accepted by the listener
+pub struct TcpConnect(pub TcpStream);
+
+/// Turn TcpConnect messaging enabled
+impl Message for TcpConnect {
+  type Result = ();
+}
+
+// Tcp Actor accepting sockets, each socket becomes a TcpSession
+pub struct TcpConnector {
+  pub lobby: Recipient<JoinMsg>,
+  pub codec: ClientActionCodec,
+}
+
+/// Turn TcpConnector into Actor enabled
+impl Actor for TcpConnector {
+  type Context = Context<Self>;
+}
+
+/// Message handling for type TcpConnect
+impl Handler<TcpConnect> for TcpConnector {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: TcpConnect, _: &mut Self::Context) {
+    let lobby = self.lobby.clone();
+    let codec = self.codec.clone();
+    TcpSession::create(move |ctx| {
+      let (r, w) = msg.0.split();
+      TcpSession::add_stream(
+        FramedRead::new(r, PlayerInfoCodec(LinesCodec::new())),
+        ctx,
+      );
+      let writer = FramedWrite::new(w, codec, ctx);
+      TcpSession {
+        lobby,
+        seat: None,
+        registered: false,
+        player: None,
+        writer,
+      }
+    });
+  }
+}
+
+// One connected socket, it takes a seat on a game, registers exactly one
+// player and receives every ClientAction broadcasted by the game hub. The
+// socket is closed when the game is over
+pub struct TcpSession {
+  pub lobby: Recipient<JoinMsg>,
+  pub seat: Option<Seat>,
+  pub registered: bool,
+  /// Name of the player entered on the game, the seat is taken for good
+  /// and the session only plays for it
+  pub player: Option<String>,
+  pub writer: FramedWrite<WriteHalf<TcpStream>, ClientActionCodec>,
+}
+
+/// Turn TcpSession into Actor enabled
+impl Actor for TcpSession {
+  type Context = Context<Self>;
+
+  fn started(&mut self, ctx: &mut Self::Context) {
+    // lines are only read after the seat is taken, the socket is closed
+    // when there is no game to play
+    ctx.wait(
+      self
+        .lobby
+        .send(JoinMsg)
+        .into_actor(self)
+        .then(|res, act, ctx| {
+          match res {
+            Ok(Ok(seat)) => {
+              // subscribe to the ClientAction broadcast
+              seat.hub.do_send(Connect(Session {
+                actions: ctx.address().recipient(),
+                disconnect: ctx.address().recipient(),
+              }));
+              act.seat = Some(seat);
+            }
+            _ => ctx.stop(),
+          }
+          fut::ok(())
+        }),
+    );
+  }
+
+  fn stopped(&mut self, _ctx: &mut Self::Context) {
+    // a socket leaving before entering it's player gives the seat back
+    if let Some(ref seat) = self.seat {
+      if self.player.is_none() {
+        seat.lobby.do_send(LeaveMsg { game: seat.game });
+      }
+    }
+  }
+}
+
+impl StreamHandler<PlayerInput, io::Error> for TcpSession {
+  fn handle(&mut self, item: PlayerInput, ctx: &mut Self::Context) {
+    match item {
+      // rematch answers and actions go to the hub, actions only count
+      // for the player of the socket
+      PlayerInput::Answer(_) | PlayerInput::Choice(_) => (),
+      // a socket plays with just one player, further lines are ignored
+      _ if self.registered => return,
+      PlayerInput::Info(ref info) => {
+        self.registered = true;
+        self.player = Some(info.name.clone());
+      }
+      // malformed lines are forwarded too so the player is asked again
+      PlayerInput::Invalid(_) => (),
+      // deadlines are kept by bpm, a socket never times out
+      PlayerInput::Timeout => return,
+    }
+    if let Some(ref seat) = self.seat {
+      seat.hub.do_send(SessionInput {
+        player: self.player.clone(),
+        input: item,
+        session: ctx.address().recipient(),
+        reject: ctx.address().recipient(),
+      });
+    }
+  }
+}
+
+impl WriteHandler<io::Error> for TcpSession {}
+
+/// Message handling for type Reject
+impl Handler<Reject> for TcpSession {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, _msg: Reject, _: &mut Self::Context) {
+    // the player never entered the game, the seat is still free for it
+    self.registered = false;
+    self.player = None;
+  }
+}
+
+/// Message handling for type Disconnect
+impl Handler<Disconnect> for TcpSession {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, _msg: Disconnect, _: &mut Self::Context) {
+    // the session stops once the last messages are written
+    self.writer.close();
+  }
+}
+
+/// Message handling for type ClientAction
+impl Handler<ClientAction> for TcpSession {
+  type Result = Result<(), Error>;
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: ClientAction, _: &mut Self::Context) -> Self::Result {
+    match msg {
+      // only sockets without a player are asked for one
+      ClientAction::AskPlayerInfo(_) if self.registered => (),
+      // after the battle a new game may enter the player again
+      ClientAction::Winner { .. } => {
+        self.registered = false;
+        self.writer.write(msg);
+      }
+      _ => self.writer.write(msg),
+    }
+    Ok(())
+  }
+}
+
+/// Binds a tcp listener and starts accepting sockets, every socket asks
+/// the lobby for a seat and feeds player info into the channel of it's game.
+/// Returns the bound address, useful when binding to port 0
+pub fn listen(
+  addr: &SocketAddr,
+  lobby: Recipient<JoinMsg>,
+  codec: ClientActionCodec,
+) -> Result<SocketAddr, Error> {
+  let listener = TcpListener::bind(addr)?;
+  let local_addr = listener.local_addr()?;
+  TcpConnector::create(move |ctx| {
+    ctx.add_message_stream(listener.incoming().map_err(|_| ()).map(TcpConnect));
+    TcpConnector { lobby, codec }
+  });
+  Ok(local_addr)
+}
@@ -0,0 +1,172 @@
+use actix::{
+  fut, Actor, ActorContext, ActorFuture, AsyncContext, Handler, Recipient, StreamHandler,
+  WrapFuture,
+};
+use actix_web::{self, server, ws, App, HttpRequest, HttpResponse};
+use core::{ClientAction, PlayerInfo, PlayerInput};
+use failure::Error;
+use serde_json;
+use std::net::SocketAddr;
+use {Connect, Disconnect, JoinMsg, LeaveMsg, Reject, Seat, Session, SessionInput};
+
+/// State shared by every websocket request
+pub struct WsState {
+  pub lobby: Recipient<JoinMsg>,
+}
+
+// One connected websocket, it takes a seat on a game, registers exactly
+// one player and receives every ClientAction broadcasted by the game hub
+// serialized as json. The websocket is closed when the game is over
+pub struct WsSession {
+  pub lobby: Recipient<JoinMsg>,
+  pub seat: Option<Seat>,
+  pub registered: bool,
+  /// Name of the player entered on the game, the seat is taken for good
+  /// and the session only plays for it
+  pub player: Option<String>,
+}
+
+/// Turn WsSession into Actor enabled
+impl Actor for WsSession {
+  type Context = ws::WebsocketContext<Self, WsState>;
+
+  fn started(&mut self, ctx: &mut Self::Context) {
+    // frames are only read after the seat is taken, the websocket is
+    // closed when there is no game to play
+    ctx.wait(
+      self
+        .lobby
+        .send(JoinMsg)
+        .into_actor(self)
+        .then(|res, act, ctx| {
+          match res {
+            Ok(Ok(seat)) => {
+              // subscribe to the ClientAction broadcast
+              seat.hub.do_send(Connect(Session {
+                actions: ctx.address().recipient(),
+                disconnect: ctx.address().recipient(),
+              }));
+              act.seat = Some(seat);
+            }
+            _ => ctx.stop(),
+          }
+          fut::ok(())
+        }),
+    );
+  }
+
+  fn stopped(&mut self, _ctx: &mut Self::Context) {
+    // a websocket leaving before entering it's player gives the seat back
+    if let Some(ref seat) = self.seat {
+      if self.player.is_none() {
+        seat.lobby.do_send(LeaveMsg { game: seat.game });
+      }
+    }
+  }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for WsSession {
+  fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+    match msg {
+      ws::Message::Ping(m) => ctx.pong(&m),
+      ws::Message::Text(text) => {
+        // player info is accepted as json or as the "name energy power" line
+        let input = match serde_json::from_str::<PlayerInfo>(&text) {
+          Ok(info) => PlayerInput::Info(info),
+          Err(_) => PlayerInput::from(text.as_ref()),
+        };
+        match input {
+          // rematch answers and actions go to the hub, actions only
+          // count for the player of the websocket
+          PlayerInput::Answer(_) | PlayerInput::Choice(_) => (),
+          // a websocket plays with just one player, further submissions are ignored
+          _ if self.registered => return,
+          PlayerInput::Info(ref info) => {
+            self.registered = true;
+            self.player = Some(info.name.clone());
+          }
+          // malformed submissions are forwarded too so the player is asked again
+          PlayerInput::Invalid(_) => (),
+          // deadlines are kept by bpm, a websocket never times out
+          PlayerInput::Timeout => return,
+        }
+        if let Some(ref seat) = self.seat {
+          seat.hub.do_send(SessionInput {
+            player: self.player.clone(),
+            input,
+            session: ctx.address().recipient(),
+            reject: ctx.address().recipient(),
+          });
+        }
+      }
+      ws::Message::Close(_) => ctx.stop(),
+      _ => (),
+    }
+  }
+}
+
+/// Message handling for type ClientAction
+impl Handler<ClientAction> for WsSession {
+  type Result = Result<(), Error>;
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, msg: ClientAction, ctx: &mut Self::Context) -> Self::Result {
+    match msg {
+      // only sockets without a player are asked for one
+      ClientAction::AskPlayerInfo(_) if self.registered => (),
+      // after the battle a new game may enter the player again
+      ClientAction::Winner { .. } => {
+        self.registered = false;
+        ctx.text(serde_json::to_string(&msg)?);
+      }
+      _ => ctx.text(serde_json::to_string(&msg)?),
+    }
+    Ok(())
+  }
+}
+
+/// Message handling for type Reject
+impl Handler<Reject> for WsSession {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, _msg: Reject, _: &mut Self::Context) {
+    // the player never entered the game, the seat is still free for it
+    self.registered = false;
+    self.player = None;
+  }
+}
+
+/// Message handling for type Disconnect
+impl Handler<Disconnect> for WsSession {
+  type Result = ();
+
+  #[cfg_attr(feature = "flame_it", flame)]
+  fn handle(&mut self, _msg: Disconnect, ctx: &mut Self::Context) {
+    ctx.close(None);
+    ctx.stop();
+  }
+}
+
+fn ws_index(req: &HttpRequest<WsState>) -> Result<HttpResponse, actix_web::Error> {
+  let session = WsSession {
+    lobby: req.state().lobby.clone(),
+    seat: None,
+    registered: false,
+    player: None,
+  };
+  ws::start(req, session)
+}
+
+/// Binds a http server upgrading `/ws/` requests to websockets, every
+/// websocket asks the lobby for a seat and feeds player info into the
+/// channel of it's game
+pub fn listen(addr: &SocketAddr, lobby: Recipient<JoinMsg>) -> Result<(), Error> {
+  server::new(move || {
+    App::with_state(WsState {
+      lobby: lobby.clone(),
+    }).resource("/ws/", |r| r.f(ws_index))
+  }).bind(addr)?
+    .start();
+  Ok(())
+}
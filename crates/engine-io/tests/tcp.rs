@@ -0,0 +1,164 @@
+extern crate actix;
+extern crate core;
+extern crate crossbeam_channel as channel;
+extern crate engine_io;
+extern crate failure;
+extern crate i18n;
+
+use actix::prelude::*;
+use core::{ClientAction, PlayerInput};
+use engine_io::{
+    tcp, ClientActionCodec, EnginePipeIo, EnterMsg, JoinMsg, LeaveMsg, OutputFormat, Seat,
+};
+use i18n::Catalog;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+
+// Stops the System from outside the reactor thread
+struct Stopper;
+
+impl Actor for Stopper {
+    type Context = Context<Self>;
+}
+
+struct Stop;
+
+impl Message for Stop {
+    type Result = ();
+}
+
+impl Handler<Stop> for Stopper {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Stop, _ctx: &mut Self::Context) {
+        System::current().stop();
+    }
+}
+
+// Seats every socket on the same game
+struct Lobby(Seat);
+
+impl Actor for Lobby {
+    type Context = Context<Self>;
+}
+
+impl Handler<JoinMsg> for Lobby {
+    type Result = Result<Seat, failure::Error>;
+
+    fn handle(&mut self, _msg: JoinMsg, _ctx: &mut Self::Context) -> Self::Result {
+        Ok(self.0.clone())
+    }
+}
+
+impl Handler<LeaveMsg> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, _msg: LeaveMsg, _ctx: &mut Self::Context) {}
+}
+
+#[test]
+fn tcp_socket_enters_player_and_receives_broadcast() {
+    let sys = System::new("tcp-test");
+    let (sender, r) = channel::bounded::<PlayerInput>(10);
+    let catalog = Arc::new(Catalog::load(i18n::DEFAULT_LOCALE, None).unwrap());
+    let codec = ClientActionCodec::new(OutputFormat::Text, catalog);
+    let hub_codec = codec.clone();
+    let hub: Addr<EnginePipeIo> =
+        EnginePipeIo::create(move |_ctx| EnginePipeIo::new(hub_codec, sender));
+    let seated = hub.clone();
+    let lobby = Lobby::create(move |ctx| {
+        Lobby(Seat {
+            game: 1,
+            hub: seated,
+            lobby: ctx.address().recipient(),
+        })
+    });
+    let addr = tcp::listen(&"127.0.0.1:0".parse().unwrap(), lobby.recipient(), codec).unwrap();
+    let stopper = Stopper.start();
+
+    let client = thread::spawn(move || {
+        let mut socket = TcpStream::connect(addr).unwrap();
+        socket.write_all(b"A 100 50\n").unwrap();
+        let input = r.recv().unwrap();
+        hub.do_send(ClientAction::Start);
+        let mut line = String::new();
+        BufReader::new(socket).read_line(&mut line).unwrap();
+        stopper.do_send(Stop);
+        (input, line)
+    });
+
+    let _ = sys.run();
+    let (input, line) = client.join().unwrap();
+    match input {
+        PlayerInput::Info(player) => {
+            assert_eq!(player.name, "A");
+            assert_eq!(player.energy, 100);
+            assert_eq!(player.power, 50);
+        }
+        i => panic!("{:?}", i),
+    }
+    assert_eq!(line.trim(), format!("{}", ClientAction::Start));
+}
+
+#[test]
+fn tcp_socket_enters_another_player_after_a_rejected_one() {
+    let sys = System::new("tcp-reject-test");
+    let (sender, r) = channel::bounded::<PlayerInput>(10);
+    let catalog = Arc::new(Catalog::load(i18n::DEFAULT_LOCALE, None).unwrap());
+    let codec = ClientActionCodec::new(OutputFormat::Text, catalog.clone());
+    let hub_codec = codec.clone();
+    let hub: Addr<EnginePipeIo> =
+        EnginePipeIo::create(move |_ctx| EnginePipeIo::new(hub_codec, sender));
+    let seated = hub.clone();
+    let lobby = Lobby::create(move |ctx| {
+        Lobby(Seat {
+            game: 1,
+            hub: seated,
+            lobby: ctx.address().recipient(),
+        })
+    });
+    let addr = tcp::listen(&"127.0.0.1:0".parse().unwrap(), lobby.recipient(), codec).unwrap();
+    let stopper = Stopper.start();
+
+    let client = thread::spawn(move || {
+        let mut socket = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        // the engine doesn't know the class and asks the seat again
+        socket.write_all(b"A wizard\n").unwrap();
+        let rejected = r.recv().unwrap();
+        hub.do_send(EnterMsg {
+            player: String::from("A"),
+            rejected: Some(String::from("Unknown class wizard")),
+        });
+        hub.do_send(ClientAction::AskPlayerInfo(1));
+        let mut lines = vec![String::new(), String::new()];
+        for line in &mut lines {
+            reader.read_line(line).unwrap();
+        }
+        // the socket is asked again and enters another player
+        socket.write_all(b"A 100 50\n").unwrap();
+        let entered = r.recv().unwrap();
+        stopper.do_send(Stop);
+        (rejected, lines, entered)
+    });
+
+    let _ = sys.run();
+    let (rejected, lines, entered) = client.join().unwrap();
+    match rejected {
+        PlayerInput::Info(player) => assert_eq!(player.class, Some(String::from("wizard"))),
+        i => panic!("{:?}", i),
+    }
+    let message = catalog.render(&ClientAction::Message(String::from("Unknown class wizard")));
+    assert_eq!(lines[0].trim(), message);
+    assert_eq!(lines[1].trim(), catalog.render(&ClientAction::AskPlayerInfo(1)));
+    match entered {
+        PlayerInput::Info(player) => {
+            assert_eq!(player.name, "A");
+            assert_eq!(player.energy, 100);
+            assert_eq!(player.class, None);
+        }
+        i => panic!("{:?}", i),
+    }
+}
@@ -1,9 +1,49 @@
-use super::GameContext;
+use super::{EngineError, GameContext};
+use actix::Addr;
 use core::{
-    BattleAnnounceMsg, BattleTurnMsg, BattleWarmUpMsg, GetPlayerInfoMsg, PlayerInfo, WinnerMsg,
+    check_player_abilities, equip, expand_class, BattleAnnounceMsg, BattleTurnMsg, BattleWarmUpMsg, ClientAction, GetPlayerInfoMsg,
+    PlayerInfo, PlayerInput, RematchMsg, TimeoutPolicy, WinnerMsg,
 };
+use engine_io::{EnginePipeIo, EnterMsg};
+use fconfig::AppConfig;
+use futures::future;
 use failure::Error;
 use futures::Future;
+use std::time::Duration;
+
+// tells the players how the game goes on after a timeout
+fn announce(io: &Addr<EnginePipeIo>, action: ClientAction) {
+    io.do_send(action);
+}
+
+/// The seat of a player not entering it's info in time goes to a stand-in
+/// or is given up, as told by the timeout policy. Returns the announcement
+/// for the players and whether the battle goes on, a battle given up
+/// below two players is aborted
+pub fn seat_timeout(
+    config: &AppConfig,
+    info: &mut Vec<PlayerInfo>,
+    players: &mut usize,
+) -> (ClientAction, bool) {
+    let seat = info.len();
+    match config.on_timeout.unwrap_or_default() {
+        TimeoutPolicy::Default => {
+            let mut stand_in = PlayerInfo::stand_in(seat);
+            stand_in.team = config.teams.get(seat).cloned();
+            let announcement = ClientAction::StandIn {
+                seat: seat + 1,
+                player_name: stand_in.name.clone(),
+            };
+            info.push(stand_in);
+            (announcement, true)
+        }
+        TimeoutPolicy::Forfeit if seat >= 2 => {
+            *players = seat;
+            (ClientAction::Forfeit { seat: seat + 1, players: seat }, true)
+        }
+        _ => (ClientAction::SeatAborted(seat + 1), false),
+    }
+}
 
 // promise / future generation
 // it gets the BPM mailbox Addr and use the send method to deliver
@@ -15,6 +55,8 @@ pub fn bpm_get_player_info_future(
     msg: GetPlayerInfoMsg,
     context: GameContext,
     mut info: Vec<PlayerInfo>,
+    mut players: usize,
+    mut retries: usize,
 ) -> Box<Future<Item = super::AfterWaitPlayerInfo, Error = Error>> {
     Box::new(
         context
@@ -22,11 +64,66 @@ pub fn bpm_get_player_info_future(
             .send(msg) // sending message promise / future
             .map_err(Into::into) // error conversion
             .and_then(|r| r) // flattening
-            .and_then(move |p| { // response from bpm
-                // include new Player Info on list
-                let _ = info.push(p);
-                // get players number on App configuration
-                let players = context.config.players.unwrap();
+            .and_then(move |input| { // response from bpm
+                // a player naming a class is expanded from the catalog and
+                // equipped, a player without items takes the loadout of it's
+                // seat. Seats are taken in the order the info arrives, the
+                // next one is info.len(). An unknown class, ability or item
+                // is asked again like any malformed info, the session entering
+                // the player hears whether it was rejected
+                let input = match input {
+                    PlayerInput::Info(p) => {
+                        let player = p.name.clone();
+                        let checked = expand_class(&context.config.classes, p)
+                            .and_then(|p| check_player_abilities(&context.config.abilities, p))
+                            .and_then(|mut p| {
+                                if p.items.is_empty() {
+                                    if let Some(loadout) = context.config.loadouts.get(info.len()) {
+                                        p.items = loadout.clone();
+                                    }
+                                }
+                                equip(&context.config.items, p)
+                            });
+                        context.io.do_send(EnterMsg {
+                            player,
+                            rejected: checked.as_ref().err().map(|e| e.to_string()),
+                        });
+                        match checked {
+                            Ok(p) => PlayerInput::Info(p),
+                            Err(e) => PlayerInput::Invalid(e.to_string()),
+                        }
+                    }
+                    input => input,
+                };
+                match input {
+                    PlayerInput::Info(mut p) => {
+                        // a player without a team takes the one of it's seat
+                        if p.team.is_none() {
+                            p.team = context.config.teams.get(info.len()).cloned();
+                        }
+                        // include new Player Info on list
+                        let _ = info.push(p);
+                        retries = 0;
+                    }
+                    PlayerInput::Answer(_) | PlayerInput::Choice(_) | PlayerInput::Invalid(_) => {
+                        // bpm, or the class expansion, already explained the
+                        // error, the same player is asked again until the retry limit
+                        retries += 1;
+                        let limit = context.config.input_retries.unwrap_or(0);
+                        if retries > limit {
+                            return Err(EngineError::InvalidInput(retries).into());
+                        }
+                    }
+                    PlayerInput::Timeout => {
+                        // a forfeit only lowers the players of this game
+                        let (announcement, goes_on) = seat_timeout(&context.config, &mut info, &mut players);
+                        announce(&context.io, announcement);
+                        if !goes_on {
+                            return Err(EngineError::Timeout(String::from("player info")).into());
+                        }
+                        retries = 0;
+                    }
+                }
                 // get current number of players
                 let current_players = info.len();
                 // initialize next state with it's handler promise
@@ -38,6 +135,7 @@ pub fn bpm_get_player_info_future(
                         },
                         context,
                         info,
+                        retries,
                     ),
                 };
                 // return new game state
@@ -50,7 +148,10 @@ pub fn bpm_battle_warm_up_future(
     msg: BattleWarmUpMsg,
     context: GameContext,
     info: Vec<PlayerInfo>,
+    retries: usize,
 ) -> Box<Future<Item = super::AfterWarmUp, Error = Error>> {
+    // players of this game, fewer than configured after a forfeit
+    let players = msg.players;
     Box::new(
         context
             .bpm
@@ -63,9 +164,13 @@ pub fn bpm_battle_warm_up_future(
                     // and bpm successfully returned
                     let player_info = super::WaitPlayerInfo {
                         handler: bpm_get_player_info_future(
-                            GetPlayerInfoMsg {},
+                            GetPlayerInfoMsg {
+                                timeout: context.config.player_info_timeout.map(Duration::from_secs),
+                            },
                             context,
                             info,
+                            players,
+                            retries,
                         ),
                     };
                     Ok(super::AfterWarmUp::WaitPlayerInfo(player_info))
@@ -74,16 +179,17 @@ pub fn bpm_battle_warm_up_future(
                     // and bpm successfully returned
                     // use of functional map reduce to initialize players HP
                     let hp: Vec<u64> = info.iter().map(|i| i.energy).collect();
-                    // use of functional map reduce to initialize players names
-                    let player_names = info.iter().map(|i| i.name.clone()).collect();
+                    // get attack table from App configuration
+                    let attacks = context.config.attacks.clone();
+                    let first = BattleTurnMsg {
+                        targeting: context.config.targeting,
+                        turn_order: context.config.turn_order,
+                        abilities: context.config.abilities.clone(),
+                        items: context.config.items.clone(),
+                        ..BattleTurnMsg::new(attacks, info.clone(), hp)
+                    };
                     let battle_data = super::BattleAnnounce {
-                        handler: bpm_battle_announce_future(
-                            BattleAnnounceMsg(player_names),
-                            context,
-                            info,
-                            hp,
-                            0, // next turn
-                        ),
+                        handler: bpm_battle_announce_future(BattleAnnounceMsg(first), context, info),
                     };
                     Ok(super::AfterWarmUp::BattleAnnounce(battle_data))
                 }
@@ -95,8 +201,6 @@ pub fn bpm_battle_announce_future(
     msg: BattleAnnounceMsg,
     context: GameContext,
     info: Vec<PlayerInfo>,
-    hp: Vec<u64>,
-    turn: usize,
 ) -> Box<Future<Item = super::AfterBattleAnnounce, Error = Error>> {
     Box::new(
         context
@@ -104,20 +208,10 @@ pub fn bpm_battle_announce_future(
             .send(msg)
             .map_err(Into::into)
             .and_then(|r| r)
-            .and_then(move |_p| {
-                // get dice cluster of ranges from App configuration
-                let range = context.config.range.clone();
+            .and_then(move |first| {
+                // the first turn comes back opened by the turn order
                 let warm_up = super::BattleOn {
-                    handler: bpm_battle_turn_future(
-                        BattleTurnMsg {
-                            range,
-                            info: info.clone(),
-                            hp,
-                            turn,
-                        },
-                        context,
-                        info,
-                    ),
+                    handler: bpm_battle_turn_future(first, context, info),
                 };
                 Ok(super::AfterBattleAnnounce::BattleOn(warm_up))
             }),
@@ -129,6 +223,8 @@ pub fn bpm_battle_turn_future(
     context: GameContext,
     info: Vec<PlayerInfo>,
 ) -> Box<Future<Item = super::AfterBattleOn, Error = Error>> {
+    // keep the turn counter as the message is moved to bpm
+    let number = msg.number;
     Box::new(
         context
             .bpm
@@ -136,27 +232,39 @@ pub fn bpm_battle_turn_future(
             .map_err(Into::into)
             .and_then(|r| r)
             .and_then(move |b| {
-                if let Some((w, hp)) = b.winner {
-                    // There is a winner
-                    // get player name from Player Info list
-                    let player = info[w].name.clone();
+                if let Some((w, _)) = b.winner {
+                    // There is a winner, with it's team in team battles
                     let over = super::BattleOver {
                         handler: bpm_battle_over_future(
-                            WinnerMsg(player, hp),
+                            WinnerMsg {
+                                winner: w,
+                                info: info.clone(),
+                                hp: b.hp.clone(),
+                            },
                             context,
+                            info,
                         ),
                     };
                     Ok(super::AfterBattleOn::BattleOver(over))
                 } else {
                     // No winner, call next turn
-                    let range = context.config.range.clone();
+                    let attacks = context.config.attacks.clone();
                     let battle_on = super::BattleOn {
                         handler: bpm_battle_turn_future(
                             BattleTurnMsg {
-                                range,
-                                info: info.clone(),
-                                hp: b.hp.clone(),
-                                turn: b.next_turn.clone(),
+                                turn: b.next_turn,
+                                number: number + 1,
+                                targeting: context.config.targeting,
+                                defending: b.defending.clone(),
+                                turn_order: context.config.turn_order,
+                                schedule: b.schedule.clone(),
+                                effects: b.effects.clone(),
+                                abilities: context.config.abilities.clone(),
+                                mana: b.mana.clone(),
+                                cooldowns: b.cooldowns.clone(),
+                                items: context.config.items.clone(),
+                                inventory: b.inventory.clone(),
+                                ..BattleTurnMsg::new(attacks, info.clone(), b.hp.clone())
                             },
                             context,
                             info,
@@ -171,7 +279,9 @@ pub fn bpm_battle_turn_future(
 pub fn bpm_battle_over_future(
     msg: WinnerMsg,
     context: GameContext,
+    info: Vec<PlayerInfo>,
 ) -> Box<Future<Item = super::AfterBattleOver, Error = Error>> {
+    let bpm = context.bpm.clone();
     Box::new(
         context
             .bpm
@@ -179,8 +289,54 @@ pub fn bpm_battle_over_future(
             .map_err(Into::into)
             .and_then(|r| r)
             .and_then(move |_p| {
-                // Game finished, this will end the stream processing
-                Ok(super::AfterBattleOver::Finished(super::Finished(())))
+                let games = context.series.games + 1;
+                let rematch = context.config.rematch.unwrap_or(false);
+                // without rematch only a configured series is played again
+                let series = match context.config.max_games {
+                    Some(max) => games < max,
+                    None => rematch,
+                };
+                let again: Box<Future<Item = bool, Error = Error>> = if !series {
+                    Box::new(future::ok(false))
+                } else if rematch {
+                    let timeout = context.config.rematch_timeout.map(Duration::from_secs);
+                    let policy = context.config.on_timeout.unwrap_or_default();
+                    let io = context.io.clone();
+                    Box::new(
+                        bpm.send(RematchMsg { timeout })
+                            .map_err(Into::into)
+                            .and_then(|r| r)
+                            .and_then(move |answer| match answer {
+                                Some(again) => Ok(again),
+                                // nobody answering is a no, unless idle
+                                // players abort the game
+                                None if policy == TimeoutPolicy::Abort => {
+                                    announce(&io, ClientAction::RematchAborted);
+                                    Err(EngineError::Timeout(String::from("rematch answer")).into())
+                                }
+                                None => {
+                                    announce(&io, ClientAction::RematchTimeout);
+                                    Ok(false)
+                                }
+                            }),
+                    )
+                } else {
+                    Box::new(future::ok(true))
+                };
+                again.map(move |again| {
+                    if again {
+                        // Battle restarts, players are entered again unless reused
+                        let mut context = context;
+                        context.series.games = games;
+                        if context.config.reuse_players.unwrap_or(true) {
+                            context.series.players = info;
+                        }
+                        super::AfterBattleOver::Start(super::Start(context))
+                    } else {
+                        // Game finished, this will end the stream processing
+                        super::AfterBattleOver::Finished(super::Finished(()))
+                    }
+                })
             }),
     )
 }
@@ -29,14 +29,19 @@ extern crate tokio;
 
 pub mod integration;
 
-use actix::{Actor, Addr, Arbiter, Context, Handler, System};
+use actix::actors::signal::{Signal, SignalType};
+use actix::{
+    fut, Actor, ActorFuture, Addr, Arbiter, AsyncContext, Context, Handler, Message, System,
+    WrapFuture,
+};
 use bpm::Bpm;
 use fconfig::AppConfig;
-use core::{BattleWarmUpMsg, StartBattleMsg};
-use engine_io::EnginePipeIo;
+use core::{BattleWarmUpMsg, PlayerInfo, StartBattleMsg};
+use engine_io::{CloseMsg, EnginePipeIo, JoinMsg, LeaveMsg, Seat};
 use futures::{Async, Future, Poll};
 use state_machine_future::RentToOwn;
-use std::io;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
 
 #[derive(Debug, Fail)]
 /// Set of errors that can occurr during engine processing
@@ -45,6 +50,42 @@ pub enum EngineError {
     Io(#[cause] io::Error),
     #[fail(display = "{}", _0)]
     Msg(String),
+    #[fail(display = "Battle aborted after {} invalid player info", _0)]
+    InvalidInput(usize),
+    #[fail(display = "Battle aborted, no {} in time", _0)]
+    Timeout(String),
+}
+
+/// Process exit code when the battle is aborted by invalid player input
+pub const EXIT_INVALID_INPUT: i32 = 3;
+
+/// Process exit code when the battle is aborted by an idle player
+pub const EXIT_TIMEOUT: i32 = 4;
+
+impl EngineError {
+    /// Process exit code reporting the error
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            EngineError::InvalidInput(_) => EXIT_INVALID_INPUT,
+            EngineError::Timeout(_) => EXIT_TIMEOUT,
+            _ => 1,
+        }
+    }
+}
+
+/// Process exit code reporting a game error
+pub fn exit_code(e: &failure::Error) -> i32 {
+    e.downcast_ref::<EngineError>()
+        .map(EngineError::exit_code)
+        .unwrap_or(1)
+}
+
+/// Games played so far on a series of rematches and the players
+/// entered on the last one, reused by the next game when configured
+#[derive(Clone, Default)]
+pub struct Series {
+    pub games: usize,
+    pub players: Vec<PlayerInfo>,
 }
 
 #[derive(Clone)]
@@ -52,6 +93,7 @@ pub struct GameContext {
     pub config: AppConfig,
     pub bpm: Addr<Bpm>,
     pub io: Addr<EnginePipeIo>,
+    pub series: Series,
 }
 
 /// To represent game state we are going to use
@@ -60,33 +102,33 @@ pub struct GameContext {
 ///
 /// ```text
 /// +----------------+
-/// |     Start      |
-/// +----------------+
-///   |
-///   |                        +-------------------------------+
-///   v                        v                               |
-/// +-----------------------------------------+                |
-/// |                 WarmUp                  | ------+        |
-/// +-----------------------------------------+       |        |
-///   |                        |                      |        |
-///   |                        |                      |        |
-///   v                        v                      v        |
-/// +----------------+       +----------------+     +-------+  |
-/// | BattleAnnounce | -+    | WaitPlayerInfo | --> | Error |  |
-/// +----------------+  |    +----------------+     +-------+  |
-///   |                 |      |                      ^        |
-///   |                 +------+----------------------+        |
-///   v                        |                               |
-/// +----------------+         |                               |
-/// |                | ---+    |                               |
-/// |    BattleOn    |    |    |                               |
-/// |                | <--+    +-------------------------------+
-/// +----------------+
-///   |
-///   |
-///   v
-/// +----------------+
-/// |   BattleOver   |
+/// |     Start      | <---------------------------------------------+
+/// +----------------+                                               |
+///   |                                                              |
+///   |                        +-------------------------------+     |
+///   v                        v                               |     |
+/// +-----------------------------------------+                |     |
+/// |                 WarmUp                  | ------+        |     |
+/// +-----------------------------------------+       |        |     |
+///   |                        |                      |        |     |
+///   |                        |                      |        |     |
+///   v                        v                      v        |     |
+/// +----------------+       +----------------+     +-------+  |     |
+/// | BattleAnnounce | -+    | WaitPlayerInfo | --> | Error |  |     |
+/// +----------------+  |    +----------------+     +-------+  |     |
+///   |                 |      |                      ^        |     |
+///   |                 +------+----------------------+        |     |
+///   v                        |                               |     |
+/// +----------------+         |                               |     |
+/// |                | ---+    |                               |     |
+/// |    BattleOn    |    |    |                               |     |
+/// |                | <--+    +-------------------------------+     |
+/// +----------------+                                               |
+///   |                                                              |
+///   |                                                              |
+///   v                                                              |
+/// +----------------+                                               |
+/// |   BattleOver   | ----------------------------------------------+
 /// +----------------+
 ///   |
 ///   |
@@ -168,9 +210,12 @@ impl PollGame for Game {
     // promise / future
     #[cfg_attr(feature = "flame_it", flame)]
     fn poll_start<'a>(data: &'a mut RentToOwn<'a, Start>) -> Poll<AfterStart, failure::Error> {
-        let context = data.take().0;
+        let mut context = data.take().0;
         let players = context.config.players.unwrap();
-        let handler = integration::bpm_battle_warm_up_future(BattleWarmUpMsg { players, current_players: 0}, context, Vec::new(), );
+        // a rematch may start with the players of the last game
+        let info = ::std::mem::replace(&mut context.series.players, Vec::new());
+        let current_players = info.len();
+        let handler = integration::bpm_battle_warm_up_future(BattleWarmUpMsg { players, current_players }, context, info, 0);
         let player_info = WarmUp {
             handler,
         };
@@ -214,8 +259,87 @@ impl PollGame for Game {
     }
 }
 
-// The Game actor with inner Game context configuration
-pub struct GameActor(pub GameContext);
+/// Id of a game hosted by the GameActor
+pub type GameId = u64;
+
+/// Builds the context of a new game with it's own IO hub and bpm
+pub type GameFactory = Box<Fn(GameId) -> Result<GameContext, failure::Error>>;
+
+/// A hosted game and how many seats network sessions took on it
+pub struct Table {
+    pub context: GameContext,
+    pub seats: usize,
+    /// Started for the network sessions joining it, instead of stdin
+    pub network: bool,
+}
+
+impl Table {
+    /// A network game every session left, nobody is playing it
+    pub fn idle(&self) -> bool {
+        self.network && self.seats == 0
+    }
+}
+
+// The Game actor hosting every running game, the process only stops
+// after a shutdown when the running games are over. Network games
+// nobody is sitting on don't hold the shutdown
+pub struct GameActor {
+    pub factory: GameFactory,
+    pub games: BTreeMap<GameId, Table>,
+    pub next_id: GameId,
+    pub shutdown: bool,
+    pub exit_code: i32,
+}
+
+impl GameActor {
+    pub fn new(factory: GameFactory) -> Self {
+        GameActor {
+            factory,
+            games: BTreeMap::new(),
+            next_id: 1,
+            shutdown: false,
+            exit_code: 0,
+        }
+    }
+
+    /// Registers a new game and spawns it's state machine
+    fn start_game(&mut self, network: bool, ctx: &mut Context<Self>) -> Result<GameId, failure::Error> {
+        if self.shutdown {
+            return Err(EngineError::Msg(String::from("Shutting down, no new games")).into());
+        }
+        let id = self.next_id;
+        let context = (self.factory)(id)?;
+        self.next_id += 1;
+        let game = ctx.address();
+        // spawn will launch a new green / light thread to process the game stream
+        // spawn is async and returns immediately, when the stream ends the
+        // game is removed from the registry with an exit code reporting errors
+        Arbiter::spawn(Game::start(context.clone()).then(move |r| {
+            let code = match r {
+                Ok(_) => 0,
+                Err(e) => {
+                    let _ = writeln!(&mut io::stderr(), "{}", e);
+                    exit_code(&e)
+                }
+            };
+            game.do_send(GameEndedMsg { id, code });
+            Ok(())
+        }));
+        self.games.insert(
+            id,
+            Table { context, seats: 0, network },
+        );
+        Ok(id)
+    }
+
+    // Terminate main reactor running on main thread which will
+    // terminate program gracefully when there is nothing left to play
+    fn stop_when_drained(&self) {
+        if self.shutdown && self.games.values().all(Table::idle) {
+            System::current().stop_with_code(self.exit_code);
+        }
+    }
+}
 
 /// Turn GameActor into Actor enabled
 impl Actor for GameActor {
@@ -224,16 +348,137 @@ impl Actor for GameActor {
 
 /// Message handling for type StartBattle
 impl Handler<StartBattleMsg> for GameActor {
-    type Result = Result<(), failure::Error>;
+    type Result = Result<GameId, failure::Error>;
 
     #[cfg_attr(feature = "flame_it", flame)]
-    fn handle(&mut self, _msg: StartBattleMsg, _ctx: &mut Self::Context) -> Self::Result {
-        // Handle the Start game by initializing the Game stream, when stream ends sends
-        // message to to System Actor to terminate main reactor running on main thread
-        // which will terminate program gracefully
-        // spawn will launch a new green / light thread to process the game stream
-        // spawn is async and returns immediately
-        Arbiter::spawn(Game::start(self.0.clone()).and_then(|_| Ok(System::current().stop())).map_err(|_| ()).map(|_| ()));
-        Ok(())
+    fn handle(&mut self, _msg: StartBattleMsg, ctx: &mut Self::Context) -> Self::Result {
+        self.start_game(false, ctx)
+    }
+}
+
+/// Message handling for type JoinMsg
+impl Handler<JoinMsg> for GameActor {
+    type Result = Result<Seat, failure::Error>;
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn handle(&mut self, _msg: JoinMsg, ctx: &mut Self::Context) -> Self::Result {
+        // seat on the oldest game still waiting for players, a new game
+        // is started when every game is full
+        let open = self
+            .games
+            .iter()
+            .find(|&(_, t)| t.seats < t.context.config.players.unwrap_or(0))
+            .map(|(id, _)| *id);
+        let id = match open {
+            Some(id) => id,
+            None => self.start_game(true, ctx)?,
+        };
+        let table = self.games.get_mut(&id).unwrap();
+        table.seats += 1;
+        Ok(Seat {
+            game: id,
+            hub: table.context.io.clone(),
+            lobby: ctx.address().recipient(),
+        })
+    }
+}
+
+/// Message handling for type LeaveMsg
+impl Handler<LeaveMsg> for GameActor {
+    type Result = ();
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn handle(&mut self, msg: LeaveMsg, _ctx: &mut Self::Context) {
+        // the seat goes to the next session joining, a game already
+        // over is gone from the registry
+        if let Some(table) = self.games.get_mut(&msg.game) {
+            table.seats = table.seats.saturating_sub(1);
+        }
+        self.stop_when_drained();
+    }
+}
+
+/// A game state machine is over
+pub struct GameEndedMsg {
+    pub id: GameId,
+    pub code: i32,
+}
+
+/// Turn GameEnded messaging enabled
+impl Message for GameEndedMsg {
+    type Result = ();
+}
+
+/// Message handling for type GameEndedMsg
+impl Handler<GameEndedMsg> for GameActor {
+    type Result = ();
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn handle(&mut self, msg: GameEndedMsg, ctx: &mut Self::Context) {
+        if msg.code != 0 {
+            self.exit_code = msg.code;
+        }
+        let table = match self.games.remove(&msg.id) {
+            Some(table) => table,
+            None => return,
+        };
+        // the sessions of the game are closed and it's output flushed
+        // before anything else, the process may stop right after
+        ctx.wait(
+            table
+                .context
+                .io
+                .send(CloseMsg)
+                .into_actor(self)
+                .then(|_, act, _ctx| {
+                    act.stop_when_drained();
+                    fut::ok(())
+                }),
+        );
+    }
+}
+
+/// Explicit shutdown, no new games are started and the process stops
+/// after the running games are over
+pub struct ShutdownMsg;
+
+/// Turn Shutdown messaging enabled
+impl Message for ShutdownMsg {
+    type Result = ();
+}
+
+/// Message handling for type ShutdownMsg
+impl Handler<ShutdownMsg> for GameActor {
+    type Result = ();
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn handle(&mut self, _msg: ShutdownMsg, _ctx: &mut Self::Context) {
+        self.shutdown = true;
+        self.stop_when_drained();
+    }
+}
+
+/// Message handling for type Signal, the network connectors shut down on
+/// the first interrupt or terminate signal and stop right away on the next
+impl Handler<Signal> for GameActor {
+    type Result = ();
+
+    #[cfg_attr(feature = "flame_it", flame)]
+    fn handle(&mut self, msg: Signal, _ctx: &mut Self::Context) {
+        match msg.0 {
+            SignalType::Int | SignalType::Term | SignalType::Quit if self.shutdown => {
+                System::current().stop_with_code(self.exit_code);
+            }
+            SignalType::Int | SignalType::Term | SignalType::Quit => {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "Shutting down after {} running games, signal again to stop now",
+                    self.games.values().filter(|t| !t.idle()).count()
+                );
+                self.shutdown = true;
+                self.stop_when_drained();
+            }
+            _ => (),
+        }
     }
 }
@@ -1,6 +1,257 @@
+extern crate actix;
+extern crate bpm;
+extern crate core;
+extern crate crossbeam_channel as channel;
 extern crate engine;
+extern crate engine_io;
+extern crate failure;
+extern crate fconfig;
+extern crate futures;
+extern crate i18n;
+
+use actix::prelude::*;
+use bpm::{ruleset, Bpm};
+use core::{ClientAction, PlayerInfo, PlayerInput, StartBattleMsg, TimeoutPolicy};
+use engine::integration::seat_timeout;
+use engine::{GameActor, GameContext, GameFactory, Series, ShutdownMsg, EXIT_TIMEOUT};
+use engine_io::{
+    ClientActionCodec, Connect, Disconnect, EnginePipeIo, JoinMsg, LeaveMsg, OutputFormat, Seat,
+    Session,
+};
+use fconfig::AppConfig;
+use futures::{future, Future};
+use i18n::Catalog;
+use std::sync::{Arc, Mutex};
+
+type Actions = Arc<Mutex<Vec<ClientAction>>>;
+
+// Games of two players with the classic rules, seeded by their id
+fn factory() -> GameFactory {
+    factory_with(AppConfig::default())
+}
+
+fn factory_with(config: AppConfig) -> GameFactory {
+    let catalog = Arc::new(Catalog::load(i18n::DEFAULT_LOCALE, None).unwrap());
+    let codec = ClientActionCodec::new(OutputFormat::Text, catalog);
+    let rules = ruleset::by_name(ruleset::DEFAULT_RULES).unwrap();
+    Box::new(move |id| {
+        let (sender, input) = channel::bounded::<PlayerInput>(100);
+        let hub_codec = codec.clone();
+        let io = EnginePipeIo::create(move |_ctx| EnginePipeIo::new(hub_codec, sender));
+        let bpm_io = io.clone();
+        let bpm_rules = rules.clone();
+        let bpm = SyncArbiter::start(1, move || {
+            Bpm::new(bpm_io.clone(), input.clone(), id, bpm_rules.clone(), None, None)
+        });
+        Ok(GameContext {
+            config: config.clone(),
+            bpm,
+            io,
+            series: Series::default(),
+        })
+    })
+}
+
+fn seated(joins: Vec<Result<Seat, failure::Error>>) -> Vec<Seat> {
+    joins.into_iter().map(Result::unwrap).collect()
+}
+
+// Session keeping every ClientAction of it's game, a closed session
+// records an empty Message last
+struct Watcher(Actions);
+
+impl Actor for Watcher {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientAction> for Watcher {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: ClientAction, _ctx: &mut Self::Context) -> Self::Result {
+        self.0.lock().unwrap().push(msg);
+        Ok(())
+    }
+}
+
+impl Handler<Disconnect> for Watcher {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Disconnect, ctx: &mut Self::Context) {
+        self.0.lock().unwrap().push(ClientAction::Message(String::new()));
+        ctx.stop();
+    }
+}
+
+// Connects a Watcher to the game of the seat and enters it's players
+fn watch(seat: &Seat, actions: Actions, players: &[&str]) {
+    let watcher = Watcher(actions).start();
+    seat.hub.do_send(Connect(Session {
+        actions: watcher.clone().recipient(),
+        disconnect: watcher.recipient(),
+    }));
+    for player in players {
+        seat.hub.do_send(PlayerInput::Info(player.parse().unwrap()));
+    }
+}
+
+fn check_game(actions: &[ClientAction], players: &[&str]) {
+    match actions.iter().find(|a| match **a {
+        ClientAction::AnnouncePlayers(_) => true,
+        _ => false,
+    }) {
+        Some(&ClientAction::AnnouncePlayers(ref names)) => assert_eq!(names, players),
+        a => panic!("{:?}", a),
+    }
+    match actions.iter().find(|a| match **a {
+        ClientAction::Winner { .. } => true,
+        _ => false,
+    }) {
+        Some(&ClientAction::Winner { ref player_name, .. }) => {
+            assert!(players.contains(&player_name.as_str()))
+        }
+        a => panic!("{:?}", a),
+    }
+    match actions.last() {
+        Some(&ClientAction::Message(ref m)) => assert!(m.is_empty()),
+        a => panic!("{:?}", a),
+    }
+}
+
+#[test]
+fn join_fills_games_in_order_test() {
+    let sys = System::new("join-test");
+    let game = GameActor::new(factory()).start();
+    let lobby = game.clone();
+    let games = Arc::new(Mutex::new(Vec::new()));
+    let record = games.clone();
+    let joins: Vec<_> = (0..4).map(|_| game.send(JoinMsg)).collect();
+    Arbiter::spawn(
+        future::join_all(joins)
+            .and_then(move |joins| {
+                let mut seats = seated(joins);
+                // the seat left on the second game goes to the next session,
+                // otherwise a third game would start
+                lobby.do_send(LeaveMsg { game: seats[3].game });
+                lobby.send(JoinMsg).map(move |seat| {
+                    seats.push(seat.unwrap());
+                    seats
+                })
+            }).map(move |seats| {
+                *record.lock().unwrap() = seats.iter().map(|s| s.game).collect();
+                System::current().stop();
+            }).map_err(|_| ()),
+    );
+    let _ = sys.run();
+    assert_eq!(*games.lock().unwrap(), vec![1, 1, 2, 2, 2]);
+}
+
+#[test]
+fn games_are_isolated_test() {
+    let sys = System::new("isolation-test");
+    let game = GameActor::new(factory()).start();
+    let lobby = game.clone();
+    let first: Actions = Arc::new(Mutex::new(Vec::new()));
+    let second: Actions = Arc::new(Mutex::new(Vec::new()));
+    let (first_watch, second_watch) = (first.clone(), second.clone());
+    let joins: Vec<_> = (0..4).map(|_| game.send(JoinMsg)).collect();
+    Arbiter::spawn(
+        future::join_all(joins)
+            .map(move |joins| {
+                let seats = seated(joins);
+                watch(&seats[0], first_watch, &["A 100 100", "B 100 60"]);
+                watch(&seats[2], second_watch, &["C 100 100", "D 100 60"]);
+                // the process stops once both games are over
+                lobby.do_send(ShutdownMsg);
+            }).map_err(|_| ()),
+    );
+    assert_eq!(sys.run(), 0);
+    check_game(&first.lock().unwrap(), &["A", "B"]);
+    check_game(&second.lock().unwrap(), &["C", "D"]);
+}
+
+#[test]
+fn shutdown_waits_for_seated_games_test() {
+    let sys = System::new("shutdown-test");
+    let game = GameActor::new(factory()).start();
+    let lobby = game.clone();
+    let refused = Arc::new(Mutex::new(false));
+    let record = refused.clone();
+    Arbiter::spawn(
+        game.send(JoinMsg)
+            .and_then(move |seat| {
+                let seat = seat.unwrap();
+                // the seated session holds the shutdown, new games are refused
+                lobby.do_send(ShutdownMsg);
+                lobby.send(StartBattleMsg).map(move |started| {
+                    *record.lock().unwrap() = started.is_err();
+                    // nobody is playing the game once it's session leaves
+                    lobby.do_send(LeaveMsg { game: seat.game });
+                })
+            }).map_err(|_| ()),
+    );
+    assert_eq!(sys.run(), 0);
+    assert!(*refused.lock().unwrap());
+}
+
+#[test]
+fn seat_timeout_test() {
+    let mut config = AppConfig::default();
+    config.players = Some(3);
+    config.teams = vec![String::from("red"), String::from("blue")];
+    let mut info: Vec<PlayerInfo> = vec!["A 100 100".parse().unwrap()];
+    let mut players = 3;
+    // a stand-in takes the seat with it's team
+    match seat_timeout(&config, &mut info, &mut players) {
+        (ClientAction::StandIn { seat, ref player_name }, true) => {
+            assert_eq!((seat, player_name.as_str()), (2, "Bot2"))
+        }
+        a => panic!("{:?}", a),
+    }
+    assert_eq!(info[1].team, Some(String::from("blue")));
+    // the seat is given up for this game only
+    config.on_timeout = Some(TimeoutPolicy::Forfeit);
+    match seat_timeout(&config, &mut info, &mut players) {
+        (ClientAction::Forfeit { seat: 3, players: 2 }, true) => (),
+        a => panic!("{:?}", a),
+    }
+    assert_eq!((info.len(), players, config.players), (2, 2, Some(3)));
+    // a battle can't go on below two players
+    info.truncate(1);
+    match seat_timeout(&config, &mut info, &mut players) {
+        (ClientAction::SeatAborted(2), false) => (),
+        a => panic!("{:?}", a),
+    }
+    config.on_timeout = Some(TimeoutPolicy::Abort);
+    info.push(PlayerInfo::stand_in(1));
+    match seat_timeout(&config, &mut info, &mut players) {
+        (ClientAction::SeatAborted(3), false) => (),
+        a => panic!("{:?}", a),
+    }
+}
+
+// exit code of a game whose players never enter their info
+fn idle_players(policy: TimeoutPolicy) -> i32 {
+    let sys = System::new("timeout-test");
+    let mut config = AppConfig::default();
+    config.player_info_timeout = Some(0);
+    config.on_timeout = Some(policy);
+    let game = GameActor::new(factory_with(config)).start();
+    game.do_send(StartBattleMsg);
+    game.do_send(ShutdownMsg);
+    sys.run()
+}
+
+#[test]
+fn idle_players_get_stand_ins_test() {
+    assert_eq!(idle_players(TimeoutPolicy::Default), 0);
+}
+
+#[test]
+fn idle_first_players_cant_forfeit_test() {
+    assert_eq!(idle_players(TimeoutPolicy::Forfeit), EXIT_TIMEOUT);
+}
 
 #[test]
-fn it_works() {
-    assert_eq!(2 + 2, 4);
+fn idle_players_abort_test() {
+    assert_eq!(idle_players(TimeoutPolicy::Abort), EXIT_TIMEOUT);
 }
@@ -7,13 +7,20 @@
 //! and others providing cloud aware configuration loading
 
 extern crate config;
+extern crate core;
 #[macro_use]
 extern crate failure;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+use core::{
+    classic_abilities, classic_attacks, classic_classes, classic_items, Ability, Attack,
+    CharacterClass, Item, Targeting, TimeoutPolicy, TurnOrder,
+};
 use failure::Error;
+use serde::de::DeserializeOwned;
+use std::path::Path;
 
 #[derive(Debug, Fail)]
 /// Set of errors that can occurr during config process
@@ -27,14 +34,110 @@ pub enum ConfigError {
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
     pub players: Option<usize>,
-    pub range: Vec<u8>,
+    /// Attack table, the attack whose dice range has the roll fires
+    #[serde(default)]
+    pub attacks: Vec<Attack>,
+    /// Seed of the battle dice, same seed replays the same battle
+    pub seed: Option<u64>,
+    /// IO connector, `pipe` for stdin stdout, `tcp` for a multiplayer server
+    /// or `ws` for websockets
+    pub connector: Option<String>,
+    /// Address the tcp and ws connectors listen on
+    pub listen: Option<String>,
+    /// Output format, `text` to display or `jsonl` for one json ClientAction per line
+    pub output: Option<String>,
+    /// Language of the messages, like `en-US`, defaults to the `LANG` language
+    pub locale: Option<String>,
+    /// How many times a malformed player info is asked again before the battle aborts
+    pub input_retries: Option<usize>,
+    /// Ask the players for a rematch when the battle is over
+    pub rematch: Option<bool>,
+    /// Games in a series, without rematch the series is played unattended
+    pub max_games: Option<usize>,
+    /// Play the next game with the same players instead of entering them again
+    pub reuse_players: Option<bool>,
+    /// Rule set by name, `classic`, `focus`, `script` or `wasm`
+    pub rules: Option<String>,
+    /// Rhai script or wasm module playing the `script` or `wasm` rule set,
+    /// relative to the scripts folder
+    pub script: Option<String>,
+    /// Fuel a wasm module burns at most on a turn
+    pub wasm_fuel: Option<u64>,
+    /// Memory of a wasm module, in 64KiB pages
+    pub wasm_memory: Option<u32>,
+    /// How attackers pick their target, `next-alive`, `random`, `lowest-hp`,
+    /// `highest-power` or `chosen`, the rule set decides when missing
+    pub targeting: Option<Targeting>,
+    /// Who acts after a turn, `rotation`, `initiative` or `atb`, the rule
+    /// set decides when missing
+    pub turn_order: Option<TurnOrder>,
+    /// Team of every seat, in seat order, for the players not giving one
+    #[serde(default)]
+    pub teams: Vec<String>,
+    /// Ask the player on turn for it's action instead of playing automatically
+    pub interactive: Option<bool>,
+    /// Seconds the player on turn has to answer before playing automatically
+    pub action_timeout: Option<u64>,
+    /// Seconds a player has to enter it's info, waits forever when missing
+    pub player_info_timeout: Option<u64>,
+    /// Seconds an attacker has to choose it's target before the rule set
+    /// picks it, waits forever when missing
+    pub target_timeout: Option<u64>,
+    /// Seconds the players have to answer a rematch, waits forever when missing
+    pub rematch_timeout: Option<u64>,
+    /// What happens when a player doesn't answer in time, `default`,
+    /// `forfeit` or `abort`
+    pub on_timeout: Option<TimeoutPolicy>,
+    /// Players of the simulated battles as "name energy power"
+    #[serde(default)]
+    pub roster: Vec<String>,
+    /// Class catalog a player line like "Alice warrior" expands from
+    #[serde(default)]
+    pub classes: Vec<CharacterClass>,
+    /// Ability catalog the players and classes name their abilities from
+    #[serde(default)]
+    pub abilities: Vec<Ability>,
+    /// Item catalog the players carry and equip their items from
+    #[serde(default)]
+    pub items: Vec<Item>,
+    /// Items of every seat, in seat order, for the players not carrying any.
+    /// Seats are taken in the order players enter their info
+    #[serde(default)]
+    pub loadouts: Vec<Vec<String>>,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             players: Some(2),
-            range: vec![15, 70, 96, 100],
+            attacks: classic_attacks(),
+            seed: None,
+            connector: Some(String::from("pipe")),
+            listen: Some(String::from("127.0.0.1:7878")),
+            output: Some(String::from("text")),
+            locale: None,
+            input_retries: Some(3),
+            rematch: Some(false),
+            max_games: None,
+            reuse_players: Some(true),
+            rules: Some(String::from("classic")),
+            script: None,
+            wasm_fuel: Some(1_000_000),
+            wasm_memory: Some(16),
+            targeting: None,
+            turn_order: None,
+            teams: Vec::new(),
+            interactive: Some(false),
+            action_timeout: Some(30),
+            player_info_timeout: None,
+            target_timeout: None,
+            rematch_timeout: None,
+            on_timeout: Some(TimeoutPolicy::Default),
+            roster: Vec::new(),
+            classes: classic_classes(),
+            abilities: classic_abilities(),
+            items: classic_items(),
+            loadouts: Vec::new(),
         }
     }
 }
@@ -42,8 +145,9 @@ impl Default for AppConfig {
 pub fn load_config(file: &str) -> Result<AppConfig, Error> {
     let mut settings = config::Config::default();
     settings
-        // Add in `./Settings.toml`
-        .merge(config::File::with_name(file))
+        // Add in `./Settings.toml`, it's optional so the environment
+        // alone is enough to configure the app
+        .merge(config::File::with_name(file).required(false))
         .map_err(|e| Error::from(ConfigError::Inner(e)))?
         // Add in settings from the environment (with a prefix of APP)
         // Eg.. `FANTASY_DEBUG=1 ./target/fantasy` would set the `debug` key
@@ -56,6 +160,43 @@ pub fn load_config(file: &str) -> Result<AppConfig, Error> {
     Ok(app_config)
 }
 
+#[derive(Debug, Deserialize)]
+struct ClassFile {
+    classes: Vec<CharacterClass>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemFile {
+    items: Vec<Item>,
+}
+
+// contents of an optional data file, None when there is no such file
+fn load_data<T: DeserializeOwned>(file: &Path) -> Result<Option<T>, Error> {
+    if !file.is_file() {
+        return Ok(None);
+    }
+    let mut settings = config::Config::default();
+    settings
+        .merge(config::File::from(file))
+        .map_err(|e| Error::from(ConfigError::Inner(e)))?;
+    let data: T = settings
+        .try_into()
+        .map_err(|e| Error::from(ConfigError::Inner(e)))?;
+    Ok(Some(data))
+}
+
+/// Class catalog of a data file with a `[[classes]]` table for every
+/// class, None when there is no such file
+pub fn load_classes(file: &Path) -> Result<Option<Vec<CharacterClass>>, Error> {
+    Ok(load_data::<ClassFile>(file)?.map(|f| f.classes))
+}
+
+/// Item catalog of a data file with an `[[items]]` table for every item,
+/// None when there is no such file
+pub fn load_items(file: &Path) -> Result<Option<Vec<Item>>, Error> {
+    Ok(load_data::<ItemFile>(file)?.map(|f| f.items))
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
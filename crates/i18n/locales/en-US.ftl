@@ -0,0 +1,48 @@
+# United States english messages
+
+ask-player-info = Enter character { $player }
+start = The game has started
+seed = Battle seed { $seed }
+announce-players = Battle between { $players }
+player-action = { $attacker } attacked { $defender }
+attack-result = { $attack } - { $damage } HP
+winner = Game over, the winner is { $player } with { $hp } HP left
+team-winner = Game over, team { $team } won, survivors: { $survivors }
+ask-rematch = Play again? (y/n)
+ask-target = { $attacker }, choose who to attack: { $targets }
+ask-action = { $attacker }, your turn: attack, defend, heal or the name of the target { $targets } ({ $timeout }s)
+action-timeout = Time is up, { $player } plays automatically
+waiting-action = Waiting for { $player }'s action
+stand-in = Player { $seat } took too long, { $player } takes the seat
+forfeit = Player { $seat } took too long and forfeits, the battle goes on with { $players } players
+seat-aborted = Player { $seat } took too long, the battle is aborted
+rematch-timeout = No rematch answer in time, the game is over
+rematch-aborted = No rematch answer in time, the game is aborted
+defend = { $player } defends
+heal = { $player } healed, HP { $hp }
+effect-applied = { $player } gets { $effect } ({ $amount }) for { $turns } turns
+effect-tick = { $player } suffers { $effect } - { $amount } HP, HP { $hp }
+stunned = { $player } is stunned and loses the turn
+effect-expired = { $effect } on { $player } is over
+ability-used = { $player } used { $ability }: { $results }
+item-used = { $player } used { $item }: { $results }
+
+attack-miss = Missed !
+attack-standard = Standard
+attack-lucky = Lucky!!!
+attack-critical = Critical!
+
+effect-poison = Poison
+effect-stun = Stun
+effect-shield = Shield
+effect-buff = Fury
+
+ability-heavy-strike = Heavy Strike
+ability-whirlwind = Whirlwind
+ability-mend = Mend
+
+item-potion = Potion
+item-bomb = Bomb
+item-sword = Sword
+item-armor = Armor
+item-boots = Boots
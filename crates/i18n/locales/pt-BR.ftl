@@ -0,0 +1,48 @@
+# Mensagens em português do Brasil
+
+ask-player-info = Entre o personagem { $player }
+start = O jogo começou
+seed = Semente da batalha { $seed }
+announce-players = Batalha entre { $players }
+player-action = { $attacker } atacou { $defender }
+attack-result = { $attack } - { $damage } HP
+winner = Jogo acabou, o vencedor foi { $player } com HP restante de { $hp }
+team-winner = Jogo acabou, a equipe { $team } venceu, sobreviventes: { $survivors }
+ask-rematch = Jogar novamente? (s/n)
+ask-target = { $attacker }, escolha quem atacar: { $targets }
+ask-action = { $attacker }, sua vez: atacar, defender, curar ou o nome do alvo { $targets } ({ $timeout }s)
+action-timeout = Tempo esgotado, { $player } joga automaticamente
+waiting-action = Aguardando a ação de { $player }
+stand-in = Jogador { $seat } demorou demais, { $player } assume o lugar
+forfeit = Jogador { $seat } demorou demais e desiste, a batalha segue com { $players } jogadores
+seat-aborted = Jogador { $seat } demorou demais, a batalha foi abortada
+rematch-timeout = Ninguém respondeu a revanche a tempo, o jogo acabou
+rematch-aborted = Ninguém respondeu a revanche a tempo, o jogo foi abortado
+defend = { $player } se defende
+heal = { $player } se curou, HP { $hp }
+effect-applied = { $player } recebe { $effect } ({ $amount }) por { $turns } turnos
+effect-tick = { $player } sofre { $effect } - { $amount } HP, HP { $hp }
+stunned = { $player } está atordoado e perde a vez
+effect-expired = { $effect } de { $player } acabou
+ability-used = { $player } usou { $ability }: { $results }
+item-used = { $player } usou { $item }: { $results }
+
+attack-miss = Errou !
+attack-standard = Normal
+attack-lucky = Sorte!!!
+attack-critical = Crítico!
+
+effect-poison = Veneno
+effect-stun = Atordoamento
+effect-shield = Escudo
+effect-buff = Fúria
+
+ability-heavy-strike = Golpe Pesado
+ability-whirlwind = Redemoinho
+ability-mend = Cura
+
+item-potion = Poção
+item-bomb = Bomba
+item-sword = Espada
+item-armor = Armadura
+item-boots = Botas
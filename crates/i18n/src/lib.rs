@@ -0,0 +1,537 @@
+//! * I18n
+//! This is the internationalization library.
+//! It's responsability is to render client facing messages on the
+//! user language from message catalogs loaded at startup.
+//! Catalogs use a subset of [Fluent](https://projectfluent.org/) syntax,
+//! one `key = value` message per line with `{ $arg }` placeables, so
+//! moving to the full Fluent runtime later keeps the same files
+
+extern crate core;
+#[macro_use]
+extern crate failure;
+
+use core::{hit_results, AttackType, ClientAction, EffectKind};
+use failure::Error;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Language of the messages when nothing else is configured
+pub const DEFAULT_LOCALE: &str = "pt-BR";
+
+// catalogs shipped within the binary
+const BUILTIN: &[(&str, &str)] = &[
+    ("pt-BR", include_str!("../locales/pt-BR.ftl")),
+    ("en-US", include_str!("../locales/en-US.ftl")),
+];
+
+/// Messages of one language indexed by key
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    pub locale: String,
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Parses a catalog source, every non empty line not starting
+    /// with `#` must be a `key = value` message
+    pub fn parse(locale: &str, source: &str) -> Result<Catalog, Error> {
+        let mut messages = HashMap::new();
+        for (n, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.find('=') {
+                Some(i) => {
+                    let key = line[..i].trim();
+                    let value = line[i + 1..].trim();
+                    messages.insert(String::from(key), String::from(value));
+                }
+                None => {
+                    return Err(format_err!(
+                        "Invalid message on line {} of {} catalog",
+                        n + 1,
+                        locale
+                    ))
+                }
+            }
+        }
+        Ok(Catalog {
+            locale: String::from(locale),
+            messages,
+        })
+    }
+
+    /// Catalog shipped within the binary for a locale, a locale of the
+    /// same language like `en` or `en-GB` takes the shipped `en-US` one
+    pub fn builtin(locale: &str) -> Option<Catalog> {
+        let language = |tag: &str| tag.split('-').next().unwrap_or("").to_lowercase();
+        BUILTIN
+            .iter()
+            .find(|&&(l, _)| l == locale)
+            .or_else(|| BUILTIN.iter().find(|&&(l, _)| language(l) == language(locale)))
+            .map(|&(l, source)| Catalog::parse(l, source).expect("builtin catalog"))
+    }
+
+    /// Loads the catalog of a locale, a `<locale>.ftl` file found on dir
+    /// overrides the shipped messages, an unknown locale without file
+    /// falls back to the default locale
+    pub fn load(locale: &str, dir: Option<&Path>) -> Result<Catalog, Error> {
+        let file = dir.map(|d| d.join(format!("{}.ftl", locale)));
+        let mut catalog = match Catalog::builtin(locale) {
+            Some(c) => c,
+            None if file.as_ref().map_or(false, |f| f.is_file()) => Catalog {
+                locale: String::from(locale),
+                messages: HashMap::new(),
+            },
+            None => return Ok(Catalog::builtin(DEFAULT_LOCALE).expect("default catalog")),
+        };
+        if let Some(f) = file {
+            if f.is_file() {
+                let custom = Catalog::parse(locale, &fs::read_to_string(f)?)?;
+                catalog.messages.extend(custom.messages);
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// Formats the message of a key replacing it's `{ $arg }` placeables
+    pub fn format(&self, key: &str, args: &[(&str, String)]) -> Option<String> {
+        let message = self.messages.get(key)?;
+        let mut out = String::with_capacity(message.len());
+        let mut rest = message.as_str();
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            match rest[start..].find('}') {
+                Some(end) => {
+                    let placeable = rest[start + 1..start + end].trim();
+                    let arg = args
+                        .iter()
+                        .find(|&&(name, _)| placeable == format!("${}", name));
+                    match arg {
+                        Some(&(_, ref value)) => out.push_str(value),
+                        None => out.push_str(&rest[start..start + end + 1]),
+                    }
+                    rest = &rest[start + end + 1..];
+                }
+                None => {
+                    out.push_str(&rest[start..]);
+                    rest = "";
+                }
+            }
+        }
+        out.push_str(rest);
+        Some(out)
+    }
+
+    /// Display of an AttackType on the catalog language, by the message key
+    /// of the attack table or by the default key of the attack name
+    pub fn attack(&self, attack: &AttackType, key: &str) -> String {
+        let key = if key.is_empty() {
+            attack.default_key()
+        } else {
+            String::from(key)
+        };
+        self.format(&key, &[])
+            .unwrap_or_else(|| format!("{}", attack))
+    }
+
+    /// Display of a status effect on the catalog language
+    pub fn effect(&self, effect: &EffectKind) -> String {
+        self.format(&effect.key(), &[])
+            .unwrap_or_else(|| format!("{}", effect))
+    }
+
+    /// Display of an ability, or an item, on the catalog language by it's
+    /// message key, the name when the catalog has no such message
+    pub fn ability(&self, name: &str, key: &str) -> String {
+        self.format(key, &[]).unwrap_or_else(|| String::from(name))
+    }
+
+    /// Display of a ClientAction on the catalog language, messages missing
+    /// on the catalog fall back to the ClientAction Display
+    pub fn render(&self, action: &ClientAction) -> String {
+        let message = match *action {
+            ClientAction::AskPlayerInfo(ref n) => {
+                self.format("ask-player-info", &[("player", n.to_string())])
+            }
+            ClientAction::Start => self.format("start", &[]),
+            ClientAction::Seed(ref s) => self.format("seed", &[("seed", s.to_string())]),
+            ClientAction::AnnouncePlayers(ref p) => {
+                self.format("announce-players", &[("players", format!("{:?}", p))])
+            }
+            ClientAction::PlayerAction {
+                ref attacker_name,
+                ref defender_name,
+                ..
+            } => self.format(
+                "player-action",
+                &[
+                    ("attacker", attacker_name.clone()),
+                    ("defender", defender_name.clone()),
+                ],
+            ),
+            ClientAction::AttackResult {
+                ref attack,
+                ref key,
+                ref damage,
+                ..
+            } => self.format(
+                "attack-result",
+                &[("attack", self.attack(attack, key)), ("damage", damage.to_string())],
+            ),
+            ClientAction::Winner {
+                team: Some(ref team),
+                ref survivors,
+                ..
+            } => self.format(
+                "team-winner",
+                &[
+                    ("team", team.clone()),
+                    (
+                        "survivors",
+                        survivors
+                            .iter()
+                            .map(|&(ref name, hp)| format!("{} ({} HP)", name, hp))
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                    ),
+                ],
+            ),
+            ClientAction::Winner {
+                ref player_name,
+                ref hp,
+                ..
+            } => self.format(
+                "winner",
+                &[("player", player_name.clone()), ("hp", hp.to_string())],
+            ),
+            ClientAction::AskRematch => self.format("ask-rematch", &[]),
+            ClientAction::AskTarget {
+                ref attacker_name,
+                ref targets,
+            } => self.format(
+                "ask-target",
+                &[
+                    ("attacker", attacker_name.clone()),
+                    ("targets", targets.join(", ")),
+                ],
+            ),
+            ClientAction::AskAction {
+                ref attacker_name,
+                ref targets,
+                ref timeout,
+            } => self.format(
+                "ask-action",
+                &[
+                    ("attacker", attacker_name.clone()),
+                    ("targets", targets.join(", ")),
+                    ("timeout", timeout.to_string()),
+                ],
+            ),
+            ClientAction::ActionTimeout(ref p) => {
+                self.format("action-timeout", &[("player", p.clone())])
+            }
+            ClientAction::WaitingAction(ref p) => {
+                self.format("waiting-action", &[("player", p.clone())])
+            }
+            ClientAction::StandIn {
+                ref seat,
+                ref player_name,
+            } => self.format(
+                "stand-in",
+                &[("seat", seat.to_string()), ("player", player_name.clone())],
+            ),
+            ClientAction::Forfeit {
+                ref seat,
+                ref players,
+            } => self.format(
+                "forfeit",
+                &[("seat", seat.to_string()), ("players", players.to_string())],
+            ),
+            ClientAction::SeatAborted(ref seat) => {
+                self.format("seat-aborted", &[("seat", seat.to_string())])
+            }
+            ClientAction::RematchTimeout => self.format("rematch-timeout", &[]),
+            ClientAction::RematchAborted => self.format("rematch-aborted", &[]),
+            ClientAction::Defend {
+                ref player_name, ..
+            } => self.format("defend", &[("player", player_name.clone())]),
+            ClientAction::Heal {
+                ref player_name,
+                ref hp,
+                ..
+            } => self.format(
+                "heal",
+                &[("player", player_name.clone()), ("hp", hp.to_string())],
+            ),
+            ClientAction::EffectApplied {
+                ref player_name,
+                ref effect,
+                ref amount,
+                ref turns,
+            } => self.format(
+                "effect-applied",
+                &[
+                    ("player", player_name.clone()),
+                    ("effect", self.effect(effect)),
+                    ("amount", amount.to_string()),
+                    ("turns", turns.to_string()),
+                ],
+            ),
+            ClientAction::EffectTick {
+                ref player_name,
+                effect: EffectKind::Stun,
+                ..
+            } => self.format("stunned", &[("player", player_name.clone())]),
+            ClientAction::EffectTick {
+                ref player_name,
+                ref effect,
+                ref amount,
+                ref hp,
+                ..
+            } => self.format(
+                "effect-tick",
+                &[
+                    ("player", player_name.clone()),
+                    ("effect", self.effect(effect)),
+                    ("amount", amount.to_string()),
+                    ("hp", hp.to_string()),
+                ],
+            ),
+            ClientAction::EffectExpired {
+                ref player_name,
+                ref effect,
+            } => self.format(
+                "effect-expired",
+                &[("player", player_name.clone()), ("effect", self.effect(effect))],
+            ),
+            ClientAction::AbilityUsed {
+                ref player_name,
+                ref ability,
+                ref key,
+                ref hits,
+                ref heal,
+                ref hp,
+                ..
+            } => {
+                let healed = if *heal > 0 {
+                    Some((player_name.as_str(), *heal, *hp))
+                } else {
+                    None
+                };
+                self.format(
+                    "ability-used",
+                    &[
+                        ("player", player_name.clone()),
+                        ("ability", self.ability(ability, key)),
+                        ("results", hit_results(hits, healed)),
+                    ],
+                )
+            }
+            ClientAction::ItemUsed {
+                ref player_name,
+                ref item,
+                ref key,
+                ref hits,
+                ref heal,
+                ref hp,
+                ..
+            } => {
+                let healed = if *heal > 0 {
+                    Some((player_name.as_str(), *heal, *hp))
+                } else {
+                    None
+                };
+                self.format(
+                    "item-used",
+                    &[
+                        ("player", player_name.clone()),
+                        ("item", self.ability(item, key)),
+                        ("results", hit_results(hits, healed)),
+                    ],
+                )
+            }
+            ClientAction::Message(ref m) => Some(m.clone()),
+        };
+        message.unwrap_or_else(|| format!("{}", action))
+    }
+}
+
+/// Locale from the `LANG` environment variable, `en_US.UTF-8` becomes `en-US`
+pub fn locale_from_env() -> Option<String> {
+    env::var("LANG").ok().and_then(|l| normalize(&l))
+}
+
+/// Converts a posix locale name to a language tag
+pub fn normalize(lang: &str) -> Option<String> {
+    let tag = lang.split(|c| c == '.' || c == '@').next().unwrap_or("");
+    match tag {
+        "" | "C" | "POSIX" => None,
+        t => Some(t.replace('_', "-")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_catalog_matches_display_test() {
+        let catalog = Catalog::builtin(DEFAULT_LOCALE).unwrap();
+        let actions = vec![
+            ClientAction::AskPlayerInfo(1),
+            ClientAction::Start,
+            ClientAction::AnnouncePlayers(vec![String::from("A"), String::from("B")]),
+            ClientAction::AttackResult {
+                turn: 1,
+                attack: AttackType(String::from("Critical")),
+                key: String::from("attack-critical"),
+                damage: 66,
+                defender: 1,
+                hp: 34,
+            },
+            ClientAction::Winner {
+                player_name: String::from("A"),
+                hp: 34,
+                team: None,
+                survivors: Vec::new(),
+            },
+            ClientAction::Winner {
+                player_name: String::from("A"),
+                hp: 34,
+                team: Some(String::from("red")),
+                survivors: vec![(String::from("A"), 34), (String::from("C"), 12)],
+            },
+            ClientAction::AskRematch,
+            ClientAction::AskTarget {
+                attacker_name: String::from("A"),
+                targets: vec![String::from("B"), String::from("C")],
+            },
+            ClientAction::AskAction {
+                attacker_name: String::from("A"),
+                targets: vec![String::from("B")],
+                timeout: 30,
+            },
+            ClientAction::ActionTimeout(String::from("A")),
+            ClientAction::WaitingAction(String::from("A")),
+            ClientAction::StandIn {
+                seat: 2,
+                player_name: String::from("Bot2"),
+            },
+            ClientAction::Forfeit {
+                seat: 3,
+                players: 2,
+            },
+            ClientAction::SeatAborted(1),
+            ClientAction::RematchTimeout,
+            ClientAction::RematchAborted,
+            ClientAction::Defend {
+                turn: 2,
+                player: 0,
+                player_name: String::from("A"),
+            },
+            ClientAction::Heal {
+                turn: 3,
+                player: 0,
+                player_name: String::from("A"),
+                hp: 60,
+            },
+            ClientAction::EffectApplied {
+                player_name: String::from("B"),
+                effect: EffectKind::Poison,
+                amount: 5,
+                turns: 3,
+            },
+            ClientAction::EffectTick {
+                turn: 4,
+                player: 1,
+                player_name: String::from("B"),
+                effect: EffectKind::Poison,
+                amount: 5,
+                hp: 45,
+            },
+            ClientAction::EffectTick {
+                turn: 5,
+                player: 1,
+                player_name: String::from("B"),
+                effect: EffectKind::Stun,
+                amount: 0,
+                hp: 45,
+            },
+            ClientAction::EffectExpired {
+                player_name: String::from("A"),
+                effect: EffectKind::Shield,
+            },
+            ClientAction::AbilityUsed {
+                turn: 6,
+                player: 0,
+                player_name: String::from("A"),
+                ability: String::from("whirlwind"),
+                key: String::from("ability-whirlwind"),
+                hits: vec![(String::from("B"), 25, 20), (String::from("C"), 25, 0)],
+                heal: 0,
+                hp: 90,
+                mana: 35,
+            },
+            ClientAction::AbilityUsed {
+                turn: 7,
+                player: 0,
+                player_name: String::from("A"),
+                ability: String::from("mend"),
+                key: String::from("ability-mend"),
+                hits: Vec::new(),
+                heal: 25,
+                hp: 100,
+                mana: 20,
+            },
+            ClientAction::ItemUsed {
+                turn: 8,
+                player: 1,
+                player_name: String::from("B"),
+                item: String::from("potion"),
+                key: String::from("item-potion"),
+                hits: Vec::new(),
+                heal: 30,
+                hp: 50,
+            },
+        ];
+        for a in actions {
+            assert_eq!(catalog.render(&a), format!("{}", a));
+        }
+    }
+
+    #[test]
+    fn english_catalog_test() {
+        let catalog = Catalog::load("en-US", None).unwrap();
+        let action = ClientAction::AttackResult {
+            turn: 1,
+            attack: AttackType(String::from("Miss")),
+            key: String::new(),
+            damage: 0,
+            defender: 1,
+            hp: 100,
+        };
+        assert_eq!(catalog.render(&action), "Missed ! - 0 HP");
+    }
+
+    #[test]
+    fn unknown_locale_falls_back_test() {
+        let catalog = Catalog::load("xx-XX", None).unwrap();
+        assert_eq!(catalog.locale, DEFAULT_LOCALE);
+        // other regions of a shipped language take it's catalog
+        assert_eq!(Catalog::load("en", None).unwrap().locale, "en-US");
+        let british = normalize("en_GB.UTF-8").unwrap();
+        assert_eq!(Catalog::load(&british, None).unwrap().locale, "en-US");
+        assert_eq!(Catalog::load("PT", None).unwrap().locale, "pt-BR");
+    }
+
+    #[test]
+    fn normalize_test() {
+        assert_eq!(normalize("en_US.UTF-8"), Some(String::from("en-US")));
+        assert_eq!(normalize("pt_BR"), Some(String::from("pt-BR")));
+        assert_eq!(normalize("C"), None);
+    }
+}
@@ -0,0 +1,223 @@
+//! * Rules wasm
+//! Sample rule module for the `wasm` rule set, the classic rules built
+//! to WebAssembly with:
+//! `cargo build -p rules-wasm --release --target wasm32-unknown-unknown`
+//! The module is sandboxed by bpm, it's only view of the battle is the
+//! json turn message handed to `resolve_turn` and it's answer is the json
+//! turn result, see the bpm `wasm` module for the whole ABI.
+//! Message types are declared here again instead of depending on core,
+//! as the module must not link actix or any IO
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use std::mem;
+use std::slice;
+
+/// ABI version implemented by the module
+pub const ABI_VERSION: i32 = 2;
+
+#[derive(Debug, Deserialize)]
+pub struct Attack {
+    pub name: String,
+    pub from: u8,
+    pub to: u8,
+    pub mul: u64,
+    pub div: u64,
+    pub bonus: u64,
+    pub base: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayerInfo {
+    pub name: String,
+    pub energy: u64,
+    pub power: u64,
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default)]
+    pub defense: u64,
+    #[serde(default)]
+    pub evasion: u8,
+    #[serde(default)]
+    pub crit: u8,
+    #[serde(default)]
+    pub regen: u64,
+    #[serde(default)]
+    pub max_hp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BattleTurnMsg {
+    pub attacks: Vec<Attack>,
+    pub info: Vec<PlayerInfo>,
+    pub hp: Vec<u64>,
+    pub turn: usize,
+    pub number: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TurnResultMsg {
+    pub hp: Vec<u64>,
+    pub next_turn: usize,
+    pub winner: Option<(usize, u64)>,
+}
+
+// whether two players fight on the same team
+fn allies(info: &[PlayerInfo], a: usize, b: usize) -> bool {
+    match (&info[a].team, &info[b].team) {
+        (&Some(ref ta), &Some(ref tb)) => a != b && ta == tb,
+        _ => false,
+    }
+}
+
+// next player alive after the given one
+fn next_alive(hp: &[u64], from: usize) -> usize {
+    let mut p = from;
+    loop {
+        p = if p == hp.len() - 1 { 0 } else { p + 1 };
+        if hp[p] > 0 || p == from {
+            return p;
+        }
+    }
+}
+
+// next enemy alive after the player on turn, it's allies look knocked out
+fn next_enemy(info: &[PlayerInfo], hp: &[u64], turn: usize) -> usize {
+    let enemy_hp: Vec<u64> = (0..hp.len())
+        .map(|i| if allies(info, turn, i) { 0 } else { hp[i] })
+        .collect();
+    next_alive(&enemy_hp, turn)
+}
+
+// dice moved by the attributes, evasion of the defender grows the
+// lowest range of the table and crit of the attacker grows the highest
+fn attribute_dice(attacks: &[Attack], dice: u8, attacker: &PlayerInfo, defender: &PlayerInfo) -> u8 {
+    let (evasion, crit) = (defender.evasion, attacker.crit);
+    let lowest = attacks.iter().min_by_key(|a| a.from);
+    let highest = attacks.iter().max_by_key(|a| a.to);
+    match (lowest, highest) {
+        (Some(low), _) if evasion > 0 && dice < low.to.saturating_add(evasion) => low.from,
+        (_, Some(high)) if crit > 0 && dice >= high.from.saturating_sub(crit) => high.from,
+        _ => dice,
+    }
+}
+
+/// Classic rules, the attacker regenerates and hits the next enemy alive
+/// with the attack of the table fired by the dice, less the defense of
+/// the defender, then the defender strikes back, a team wins when no
+/// enemy is left
+pub fn play(msg: &BattleTurnMsg, dice: u8) -> TurnResultMsg {
+    let (info, turn) = (&msg.info, msg.turn);
+    let mut hp = msg.hp.clone();
+    let max_hp = info[turn].max_hp.max(hp[turn]);
+    hp[turn] = hp[turn].saturating_add(info[turn].regen).min(max_hp);
+    let defender = next_enemy(info, &hp, turn);
+    let dice = attribute_dice(&msg.attacks, dice, &info[turn], &info[defender]);
+    let damage = msg
+        .attacks
+        .iter()
+        .find(|a| dice >= a.from && dice < a.to)
+        .map_or(0, |a| {
+            let power = info[turn].power / a.base.max(1);
+            (power.saturating_mul(a.mul) / a.div.max(1)).saturating_add(a.bonus)
+        });
+    if defender != turn {
+        hp[defender] = hp[defender].saturating_sub(damage.saturating_sub(info[defender].defense));
+    }
+    let next_turn = if hp[defender] > 0 {
+        defender
+    } else {
+        next_alive(&hp, defender)
+    };
+    let alive = (0..hp.len()).any(|i| i != turn && hp[i] > 0 && !allies(info, turn, i));
+    TurnResultMsg {
+        winner: if alive { None } else { Some((turn, hp[turn])) },
+        hp,
+        next_turn,
+    }
+}
+
+// hands a buffer to the host, it's never freed as every turn runs on a
+// fresh module instance
+fn leak(mut buffer: Vec<u8>) -> i32 {
+    buffer.shrink_to_fit();
+    let ptr = buffer.as_mut_ptr() as usize as i32;
+    mem::forget(buffer);
+    ptr
+}
+
+#[no_mangle]
+pub extern "C" fn abi_version() -> i32 {
+    ABI_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn alloc(len: i32) -> i32 {
+    leak(vec![0; len as usize])
+}
+
+#[no_mangle]
+pub extern "C" fn resolve_turn(ptr: i32, len: i32, dice: i32) -> i64 {
+    let input = unsafe { slice::from_raw_parts(ptr as usize as *const u8, len as usize) };
+    let output = match serde_json::from_slice::<BattleTurnMsg>(input) {
+        Ok(msg) => serde_json::to_vec(&play(&msg, dice as u8)).unwrap_or_default(),
+        // an empty answer is refused by the host
+        Err(_) => Vec::new(),
+    };
+    let len = output.len() as i64;
+    (i64::from(leak(output) as u32) << 32) | len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classic_turn_test() {
+        let msg: BattleTurnMsg = serde_json::from_str(
+            r#"{"attacks":[{"name":"Critical","from":96,"to":100,"mul":2,"div":1,"bonus":0,"base":3}],
+                "info":[{"name":"A","energy":100,"power":120},{"name":"B","energy":100,"power":60}],
+                "hp":[100,100],"turn":0,"number":1}"#,
+        ).unwrap();
+        let result = play(&msg, 99);
+        assert_eq!(result.hp, vec![100, 20]);
+        assert_eq!(result.next_turn, 1);
+        assert!(result.winner.is_none());
+        assert_eq!(play(&msg, 10).hp, vec![100, 100]);
+    }
+
+    #[test]
+    fn team_turn_test() {
+        let msg: BattleTurnMsg = serde_json::from_str(
+            r#"{"attacks":[{"name":"Standard","from":0,"to":100,"mul":1,"div":1,"bonus":0,"base":3}],
+                "info":[{"name":"A","energy":100,"power":120,"team":"red"},
+                        {"name":"B","energy":100,"power":60,"team":"red"},
+                        {"name":"C","energy":100,"power":60,"team":"blue"}],
+                "hp":[100,100,40],"turn":0,"number":1}"#,
+        ).unwrap();
+        // the ally is skipped and the team wins
+        let result = play(&msg, 50);
+        assert_eq!(result.hp, vec![100, 100, 0]);
+        assert_eq!(result.winner, Some((0, 100)));
+    }
+
+    #[test]
+    fn attributes_turn_test() {
+        let msg: BattleTurnMsg = serde_json::from_str(
+            r#"{"attacks":[{"name":"Miss","from":0,"to":15,"mul":0,"div":1,"bonus":0,"base":1},
+                           {"name":"Standard","from":15,"to":70,"mul":1,"div":1,"bonus":0,"base":3},
+                           {"name":"Critical","from":96,"to":100,"mul":2,"div":1,"bonus":0,"base":3}],
+                "info":[{"name":"A","energy":100,"power":120,"crit":30,"regen":20,"max_hp":110},
+                        {"name":"B","energy":100,"power":60,"evasion":10,"defense":30,"max_hp":100}],
+                "hp":[95,100],"turn":0,"number":1}"#,
+        ).unwrap();
+        // regeneration up to the max HP, crit turns 80 into a Critical
+        // less the defense
+        assert_eq!(play(&msg, 80).hp, vec![110, 50]);
+        // evasion turns 20 into a Miss
+        assert_eq!(play(&msg, 20).hp, vec![110, 100]);
+    }
+}
@@ -62,6 +62,6 @@ fn main() {
 // Use of the forwarding pattern here to call the inner client library run
 // method, with the exception from the pattern there is no real object:
 // https://en.wikipedia.org/wiki/Forwarding_(object-oriented_programming)
-fn run() -> Result<(), failure::Error> {
+fn run() -> Result<i32, failure::Error> {
     client::run(::std::env::args())
 }